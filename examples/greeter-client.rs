@@ -79,17 +79,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     // Start endpoint discovery
-    // The build function creates an Endpoint for each discovered pod address
-    discover(config, tx, |addr| {
-        Endpoint::from_shared(format!("http://{addr}"))
+    // The build function creates an Endpoint for each discovered pod
+    let handle = discover(config, tx, |endpoint| {
+        Endpoint::from_shared(format!("http://{}", endpoint.address))
             .expect("valid endpoint URI")
             .connect_timeout(Duration::from_secs(5))
             .timeout(Duration::from_secs(10))
     });
 
-    // Wait a bit for initial endpoint discovery
+    // Wait for the initial endpoint discovery
     info!("Waiting for endpoint discovery...");
-    sleep(Duration::from_secs(3)).await;
+    if let Err(reason) = handle.ready_timeout(Duration::from_secs(30)).await {
+        error!("Endpoint discovery not ready: {reason}");
+    }
 
     // Create the gRPC client
     let mut client = GreeterClient::new(channel);
@@ -0,0 +1,695 @@
+//! Weighted load balancing.
+//!
+//! Tonic's balance channel treats all endpoints equally. The weighted channel
+//! instead routes each request to an endpoint picked at random, with a probability
+//! proportional to the endpoint's weight, using the weight carried by its
+//! [`Weighted`] key.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::future::Future;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender};
+use tokio::time::Sleep;
+use tonic::body::Body;
+use tonic::transport::channel::Change;
+use tonic::transport::{Channel, Endpoint};
+use tower::Service;
+use tower::buffer::{Buffer, future::ResponseFuture as BufferFuture};
+use tracing::{debug, warn};
+
+use crate::endpoint::Weighted;
+use crate::error::BoxError;
+
+/// Number of requests buffered in front of the balancer, as in Tonic's balance channel.
+const BUFFER_SIZE: usize = 1024;
+
+/// Delay before reconnecting to an endpoint after its first failed connection attempt.
+const INITIAL_RECONNECT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Maximum delay between connection attempts to an endpoint.
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Future returned by a balanced endpoint.
+type EndpointFuture = BoxFuture<'static, Result<http::Response<Body>, BoxError>>;
+
+/// Future connecting to an endpoint.
+type Connecting = BoxFuture<'static, Result<Channel, tonic::transport::Error>>;
+
+/// A channel balancing requests across endpoints by weight.
+///
+/// Use it instead of Tonic's balance channel when endpoints have different
+/// capacities, e.g. with weights read from pod annotations. Each request is sent to
+/// a connected endpoint picked with a probability proportional to its weight.
+///
+/// Endpoints are connected as soon as they are inserted. Endpoints that cannot be
+/// connected, or whose connection fails, receive no requests and are reconnected
+/// with exponential backoff until they are removed. An endpoint whose buffer is
+/// full is passed over for another connected endpoint.
+///
+/// # Example
+///
+/// ```ignore
+/// use std::net::SocketAddr;
+/// use tonic::transport::Endpoint;
+/// use tonic_lb_k8s::{discover, DiscoveryConfig, WeightSource, WeightedChannel};
+///
+/// let (channel, tx) = WeightedChannel::balance_channel::<SocketAddr>(1024);
+///
+/// let config = DiscoveryConfig::new("my-grpc-service", 50051)
+///     .weights(WeightSource::Annotation {
+///         key: "example.com/weight".to_string(),
+///         selector: "app=my-grpc-service".to_string(),
+///     });
+/// discover(config, tx, |endpoint| {
+///     Endpoint::from_shared(format!("http://{}", endpoint.address)).unwrap()
+/// });
+///
+/// let client = MyServiceClient::new(channel);
+/// ```
+#[derive(Clone)]
+pub struct WeightedChannel {
+    /// The balancer, shared between clones of the channel.
+    svc: Buffer<http::Request<Body>, EndpointFuture>,
+}
+
+impl fmt::Debug for WeightedChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WeightedChannel").finish_non_exhaustive()
+    }
+}
+
+impl WeightedChannel {
+    /// Creates a weighted channel and the sender of its endpoint changes.
+    ///
+    /// Must be called within a Tokio runtime, which runs the balancer.
+    #[must_use]
+    pub fn balance_channel<K>(capacity: usize) -> (Self, Sender<Change<Weighted<K>, Endpoint>>)
+    where
+        K: Hash + Eq + Send + Clone + 'static,
+    {
+        let (tx, rx) = mpsc::channel(capacity);
+        let svc = Buffer::new(WeightedBalance::new(rx, fastrand::Rng::new()), BUFFER_SIZE);
+
+        (Self { svc }, tx)
+    }
+}
+
+impl Service<http::Request<Body>> for WeightedChannel {
+    type Response = http::Response<Body>;
+    type Error = BoxError;
+    type Future = BufferFuture<EndpointFuture>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.svc.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<Body>) -> Self::Future {
+        self.svc.call(request)
+    }
+}
+
+/// The connection state of an endpoint.
+enum State {
+    /// Connecting to the endpoint.
+    Connecting(Connecting),
+
+    /// Connected, ready to receive requests once its buffer has room.
+    Connected(Channel),
+
+    /// Waiting to reconnect after a failed connection attempt.
+    Waiting(Pin<Box<Sleep>>),
+}
+
+/// An endpoint received from discovery and its connection.
+struct Backend<K> {
+    /// The key of the endpoint, carrying its weight.
+    key: Weighted<K>,
+
+    /// The endpoint, kept to reconnect.
+    endpoint: Endpoint,
+
+    /// The connection state.
+    state: State,
+
+    /// Number of consecutive failed connection attempts.
+    failures: u32,
+}
+
+impl<K> Backend<K> {
+    /// Creates a backend, starting to connect to the endpoint.
+    fn new(key: Weighted<K>, endpoint: Endpoint) -> Self {
+        Self {
+            key,
+            state: State::Connecting(connect(&endpoint)),
+            endpoint,
+            failures: 0,
+        }
+    }
+
+    /// Returns the channel of the endpoint, if it is connected.
+    fn channel(&mut self) -> Option<&mut Channel> {
+        match &mut self.state {
+            State::Connected(channel) => Some(channel),
+            _ => None,
+        }
+    }
+
+    /// Drops the connection and connects again.
+    fn reconnect(&mut self, cx: &mut Context<'_>) {
+        self.state = State::Connecting(connect(&self.endpoint));
+        self.poll_connection(cx);
+    }
+
+    /// Drives the connection until it is established or waiting.
+    fn poll_connection(&mut self, cx: &mut Context<'_>) {
+        loop {
+            match &mut self.state {
+                State::Connected(_) => return,
+                State::Connecting(connecting) => match connecting.as_mut().poll(cx) {
+                    Poll::Pending => return,
+                    Poll::Ready(Ok(channel)) => {
+                        debug!("connected to endpoint {}", self.endpoint.uri());
+                        self.failures = 0;
+                        self.state = State::Connected(channel);
+                    }
+
+                    Poll::Ready(Err(e)) => {
+                        self.failures = self.failures.saturating_add(1);
+                        let delay = reconnect_backoff(self.failures);
+                        warn!(
+                            "connecting to endpoint {} failed, retrying in {delay:?}: {e}",
+                            self.endpoint.uri()
+                        );
+
+                        self.state = State::Waiting(Box::pin(tokio::time::sleep(delay)));
+                    }
+                },
+
+                State::Waiting(sleep) => {
+                    if sleep.as_mut().poll(cx).is_pending() {
+                        return;
+                    }
+
+                    self.state = State::Connecting(connect(&self.endpoint));
+                }
+            }
+        }
+    }
+}
+
+/// Starts connecting to an endpoint.
+fn connect(endpoint: &Endpoint) -> Connecting {
+    let endpoint = endpoint.clone();
+    async move { endpoint.connect().await }.boxed()
+}
+
+/// Returns the delay before the next connection attempt, doubling with each failure.
+fn reconnect_backoff(failures: u32) -> Duration {
+    let factor = 1_u32 << failures.saturating_sub(1).min(16);
+    INITIAL_RECONNECT_BACKOFF
+        .saturating_mul(factor)
+        .min(MAX_RECONNECT_BACKOFF)
+}
+
+/// Returns whether a failed call points at a broken connection, rather than a
+/// call that timed out.
+fn is_connection_error(err: &(dyn StdError + 'static)) -> bool {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if err.is::<tonic::TimeoutExpired>() {
+            return false;
+        }
+
+        source = err.source();
+    }
+
+    true
+}
+
+/// Balances requests across the endpoints received from discovery.
+struct WeightedBalance<K> {
+    /// Receiver of endpoint changes.
+    rx: Receiver<Change<Weighted<K>, Endpoint>>,
+
+    /// The endpoints and their connections.
+    endpoints: Vec<Backend<K>>,
+
+    /// Index of the endpoint picked for the next request, once polled.
+    picked: Option<usize>,
+
+    /// Sender of the endpoints whose calls failed on a broken connection.
+    failed_tx: UnboundedSender<Weighted<K>>,
+
+    /// Receiver of the endpoints whose calls failed on a broken connection.
+    failed_rx: UnboundedReceiver<Weighted<K>>,
+
+    /// Random number generator picking endpoints.
+    rng: fastrand::Rng,
+}
+
+impl<K: Eq> WeightedBalance<K> {
+    /// Creates a balancer without endpoints.
+    fn new(rx: Receiver<Change<Weighted<K>, Endpoint>>, rng: fastrand::Rng) -> Self {
+        let (failed_tx, failed_rx) = mpsc::unbounded_channel();
+        Self {
+            rx,
+            endpoints: Vec::new(),
+            picked: None,
+            failed_tx,
+            failed_rx,
+            rng,
+        }
+    }
+
+    /// Applies all pending endpoint changes and reconnects failed endpoints.
+    fn poll_changes(&mut self, cx: &mut Context<'_>) {
+        while let Poll::Ready(Some(change)) = self.rx.poll_recv(cx) {
+            // Indices shift when endpoints change
+            self.picked = None;
+
+            match change {
+                Change::Insert(key, endpoint) => {
+                    self.remove(&key);
+                    self.endpoints.push(Backend::new(key, endpoint));
+                }
+
+                Change::Remove(key) => self.remove(&key),
+            }
+        }
+
+        while let Poll::Ready(Some(key)) = self.failed_rx.poll_recv(cx) {
+            // Calls failing together reconnect the endpoint once
+            let Some(backend) = self.endpoints.iter_mut().find(|b| b.key == key) else {
+                continue;
+            };
+
+            if backend.channel().is_some() {
+                warn!(
+                    "connection to endpoint {} failed, reconnecting",
+                    backend.endpoint.uri()
+                );
+                self.picked = None;
+                backend.reconnect(cx);
+            }
+        }
+
+        for backend in &mut self.endpoints {
+            backend.poll_connection(cx);
+        }
+    }
+
+    /// Removes an endpoint, if it is known.
+    fn remove(&mut self, key: &Weighted<K>) {
+        if let Some(index) = self.endpoints.iter().position(|b| b.key == *key) {
+            self.endpoints.swap_remove(index);
+        }
+    }
+
+    /// Picks a connected endpoint at random, weighted by its weight, passing over
+    /// the busy ones.
+    fn pick(&mut self, busy: &[usize]) -> Option<usize> {
+        let eligible = |(index, backend): &(usize, &Backend<K>)| {
+            matches!(backend.state, State::Connected(_)) && !busy.contains(index)
+        };
+
+        let total: u64 = self
+            .endpoints
+            .iter()
+            .enumerate()
+            .filter(eligible)
+            .map(|(_, backend)| u64::from(backend.key.weight))
+            .sum();
+
+        if total == 0 {
+            return None;
+        }
+
+        let mut point = self.rng.u64(..total);
+        self.endpoints
+            .iter()
+            .enumerate()
+            .filter(eligible)
+            .find(|(_, backend)| {
+                let weight = u64::from(backend.key.weight);
+                if point < weight {
+                    true
+                } else {
+                    point -= weight;
+                    false
+                }
+            })
+            .map(|(index, _)| index)
+    }
+}
+
+impl<K: Eq + Send + Clone + 'static> Service<http::Request<Body>> for WeightedBalance<K> {
+    type Response = http::Response<Body>;
+    type Error = BoxError;
+    type Future = EndpointFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_changes(cx);
+
+        // The picked endpoint holds on to the room reserved in its buffer
+        if self.picked.is_some() {
+            return Poll::Ready(Ok(()));
+        }
+
+        let mut busy = Vec::new();
+        loop {
+            // The receivers, connections and buffers polled wake the task once an
+            // endpoint may be ready
+            let Some(index) = self.pick(&busy) else {
+                debug!("no connected endpoint available, waiting");
+                return Poll::Pending;
+            };
+
+            let backend = &mut self.endpoints[index];
+            let channel = backend.channel().expect("picked endpoint is connected");
+            match channel.poll_ready(cx) {
+                Poll::Ready(Ok(())) => {
+                    self.picked = Some(index);
+                    return Poll::Ready(Ok(()));
+                }
+
+                Poll::Pending => busy.push(index),
+                Poll::Ready(Err(e)) => {
+                    warn!(
+                        "endpoint {} failed, reconnecting: {e}",
+                        backend.endpoint.uri()
+                    );
+                    backend.reconnect(cx);
+                }
+            }
+        }
+    }
+
+    fn call(&mut self, request: http::Request<Body>) -> Self::Future {
+        let index = self
+            .picked
+            .take()
+            .expect("poll_ready must be called before call");
+
+        let backend = &mut self.endpoints[index];
+        let key = backend.key.clone();
+        let failed = self.failed_tx.clone();
+        let response = backend
+            .channel()
+            .expect("picked endpoint is connected")
+            .call(request);
+
+        async move {
+            response.await.map_err(|e| {
+                if is_connection_error(&e) {
+                    // The balancer may be gone already
+                    let _ = failed.send(key);
+                }
+
+                BoxError::from(e)
+            })
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::future::{Ready, ready};
+    use std::net::SocketAddr;
+
+    use futures::task::noop_waker_ref;
+    use tonic::transport::server::TcpIncoming;
+
+    use super::*;
+
+    fn key(addr: &str, weight: u32) -> Weighted<SocketAddr> {
+        Weighted {
+            key: addr.parse().unwrap(),
+            weight,
+        }
+    }
+
+    fn endpoint(addr: &str) -> Endpoint {
+        Endpoint::from_shared(format!("http://{addr}")).unwrap()
+    }
+
+    fn balance() -> (
+        WeightedBalance<SocketAddr>,
+        Sender<Change<Weighted<SocketAddr>, Endpoint>>,
+    ) {
+        let (tx, rx) = mpsc::channel(16);
+        (WeightedBalance::new(rx, fastrand::Rng::with_seed(7)), tx)
+    }
+
+    fn apply(balance: &mut WeightedBalance<SocketAddr>) {
+        balance.poll_changes(&mut Context::from_waker(noop_waker_ref()));
+    }
+
+    // Inserts endpoints and marks them connected, without connecting
+    async fn connected(
+        balance: &mut WeightedBalance<SocketAddr>,
+        tx: &Sender<Change<Weighted<SocketAddr>, Endpoint>>,
+        endpoints: &[(&str, u32)],
+    ) {
+        for (addr, weight) in endpoints {
+            let endpoint = endpoint(addr).buffer_size(1);
+            tx.send(Change::Insert(key(addr, *weight), endpoint))
+                .await
+                .unwrap();
+        }
+
+        apply(balance);
+        for backend in &mut balance.endpoints {
+            backend.state = State::Connected(backend.endpoint.connect_lazy());
+        }
+    }
+
+    fn weights(balance: &WeightedBalance<SocketAddr>) -> Vec<u32> {
+        balance.endpoints.iter().map(|b| b.key.weight).collect()
+    }
+
+    // Returns the address of a port refusing connections
+    fn refused() -> SocketAddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    // Answers every request with an empty gRPC response
+    #[derive(Clone)]
+    struct Ok200;
+
+    impl Service<http::Request<Body>> for Ok200 {
+        type Response = http::Response<Body>;
+        type Error = Infallible;
+        type Future = Ready<Result<Self::Response, Infallible>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _request: http::Request<Body>) -> Self::Future {
+            let response = http::Response::builder()
+                .header("grpc-status", "0")
+                .body(Body::empty())
+                .unwrap();
+
+            ready(Ok(response))
+        }
+    }
+
+    fn serve() -> SocketAddr {
+        let incoming = TcpIncoming::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = incoming.local_addr().unwrap();
+        tokio::spawn(tonic::transport::Server::builder().serve_with_incoming(Ok200, incoming));
+        addr
+    }
+
+    #[tokio::test]
+    async fn balance_tracks_endpoint_changes() {
+        let (mut balance, tx) = balance();
+
+        tx.send(Change::Insert(
+            key("10.0.0.1:80", 2),
+            endpoint("10.0.0.1:80"),
+        ))
+        .await
+        .unwrap();
+        tx.send(Change::Insert(
+            key("10.0.0.2:80", 3),
+            endpoint("10.0.0.2:80"),
+        ))
+        .await
+        .unwrap();
+        apply(&mut balance);
+        assert_eq!(weights(&balance), vec![2, 3]);
+
+        tx.send(Change::Remove(key("10.0.0.1:80", 2)))
+            .await
+            .unwrap();
+        apply(&mut balance);
+        assert_eq!(weights(&balance), vec![3]);
+    }
+
+    #[tokio::test]
+    async fn balance_replaces_reinserted_endpoint() {
+        let (mut balance, tx) = balance();
+
+        for _ in 0..2 {
+            tx.send(Change::Insert(
+                key("10.0.0.1:80", 2),
+                endpoint("10.0.0.1:80"),
+            ))
+            .await
+            .unwrap();
+        }
+
+        apply(&mut balance);
+        assert_eq!(weights(&balance), vec![2]);
+    }
+
+    #[tokio::test]
+    async fn balance_picks_by_weight() {
+        let (mut balance, tx) = balance();
+        connected(&mut balance, &tx, &[("10.0.0.1:80", 1), ("10.0.0.2:80", 3)]).await;
+
+        let mut picks = [0_u32; 2];
+        for _ in 0..4000 {
+            picks[balance.pick(&[]).unwrap()] += 1;
+        }
+
+        // The heavier endpoint receives about three quarters of the requests
+        assert!((2800..3200).contains(&picks[1]), "{picks:?}");
+    }
+
+    #[test]
+    fn balance_without_endpoints_picks_none() {
+        let (mut balance, _tx) = balance();
+        assert_eq!(balance.pick(&[]), None);
+    }
+
+    #[tokio::test]
+    async fn balance_picks_only_connected_endpoints() {
+        let (mut balance, tx) = balance();
+        connected(
+            &mut balance,
+            &tx,
+            &[("10.0.0.1:80", 1), ("10.0.0.2:80", 1000)],
+        )
+        .await;
+
+        let unreachable = endpoint("10.0.0.2:80");
+        balance.endpoints[1].state = State::Connecting(connect(&unreachable));
+
+        for _ in 0..100 {
+            assert_eq!(balance.pick(&[]), Some(0));
+        }
+    }
+
+    #[tokio::test]
+    async fn balance_passes_over_busy_endpoint() {
+        let (mut balance, tx) = balance();
+        connected(
+            &mut balance,
+            &tx,
+            &[("10.0.0.1:80", 1000), ("10.0.0.2:80", 1)],
+        )
+        .await;
+
+        // Another handle takes the only slot of the heavier endpoint's buffer
+        let mut busy = balance.endpoints[0].channel().unwrap().clone();
+        let mut cx = Context::from_waker(noop_waker_ref());
+        assert!(busy.poll_ready(&mut cx).is_ready());
+
+        assert!(balance.poll_ready(&mut cx).is_ready());
+        assert_eq!(balance.picked, Some(1));
+    }
+
+    #[tokio::test]
+    async fn balance_keeps_unreachable_endpoint_to_reconnect() {
+        let (mut balance, tx) = balance();
+        let addr = refused().to_string();
+        tx.send(Change::Insert(key(&addr, 1), endpoint(&addr)))
+            .await
+            .unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while balance.endpoints.first().is_none_or(|b| b.failures == 0) {
+                apply(&mut balance);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(balance.endpoints.len(), 1);
+        assert!(matches!(balance.endpoints[0].state, State::Waiting(_)));
+        assert_eq!(balance.pick(&[]), None);
+    }
+
+    #[tokio::test]
+    async fn balance_reconnects_endpoint_after_failed_call() {
+        let (mut balance, tx) = balance();
+        connected(&mut balance, &tx, &[("10.0.0.1:80", 1)]).await;
+
+        balance.failed_tx.send(key("10.0.0.1:80", 1)).unwrap();
+        apply(&mut balance);
+
+        assert!(matches!(balance.endpoints[0].state, State::Connecting(_)));
+    }
+
+    #[tokio::test]
+    async fn channel_routes_around_unreachable_endpoint() {
+        let (mut channel, tx) = WeightedChannel::balance_channel::<SocketAddr>(16);
+        let live = serve().to_string();
+        let dead = refused().to_string();
+
+        tx.send(Change::Insert(key(&dead, 1000), endpoint(&dead)))
+            .await
+            .unwrap();
+        tx.send(Change::Insert(key(&live, 1), endpoint(&live)))
+            .await
+            .unwrap();
+
+        for _ in 0..20 {
+            let request = http::Request::builder()
+                .uri(format!("http://{live}/test.Service/Call"))
+                .body(Body::empty())
+                .unwrap();
+
+            let call = async {
+                futures::future::poll_fn(|cx| channel.poll_ready(cx)).await?;
+                channel.call(request).await
+            };
+
+            let response = tokio::time::timeout(Duration::from_secs(5), call)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(response.status(), http::StatusCode::OK);
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        assert_eq!(reconnect_backoff(1), INITIAL_RECONNECT_BACKOFF);
+        assert_eq!(reconnect_backoff(2), INITIAL_RECONNECT_BACKOFF * 2);
+        assert_eq!(reconnect_backoff(u32::MAX), MAX_RECONNECT_BACKOFF);
+    }
+
+    #[test]
+    fn timed_out_calls_keep_connection() {
+        let timeout: BoxError = Box::new(tonic::TimeoutExpired(()));
+        let reset: BoxError = Box::new(std::io::Error::from(std::io::ErrorKind::ConnectionReset));
+
+        assert!(!is_connection_error(timeout.as_ref()));
+        assert!(is_connection_error(reset.as_ref()));
+    }
+}
@@ -0,0 +1,214 @@
+//! Coalescing of endpoint changes.
+//!
+//! During a rolling deployment, endpoints are added, become ready and are removed
+//! in quick succession. With a settle window, changes are collected until the
+//! window elapses and only their net effect is published, so an endpoint removed
+//! and added back within the window keeps its connection.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::endpoint::DiscoveryKey;
+use crate::k8s::EndpointAction;
+
+/// Collects endpoint changes until the settle window elapses.
+#[derive(Debug)]
+pub(crate) struct Batch<K> {
+    /// How long changes are collected, or `None` to publish them immediately.
+    window: Option<Duration>,
+
+    /// The net change of each key since the last flush.
+    changes: HashMap<K, EndpointAction<K>>,
+
+    /// When the collected changes are due, if there are any.
+    deadline: Option<Instant>,
+}
+
+impl<K: DiscoveryKey> Batch<K> {
+    /// Creates an empty batch with the given settle window.
+    pub(crate) fn new(window: Option<Duration>) -> Self {
+        Self {
+            window,
+            changes: HashMap::new(),
+            deadline: None,
+        }
+    }
+
+    /// Returns when the collected changes are due, if there are any.
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Collects changes and returns those to publish now.
+    ///
+    /// Without a settle window, all changes are returned as is. Otherwise they
+    /// are merged into the batch, starting the window if it is not running yet.
+    pub(crate) fn add(&mut self, actions: Vec<EndpointAction<K>>) -> Vec<EndpointAction<K>> {
+        let Some(window) = self.window else {
+            return actions;
+        };
+
+        for action in actions {
+            self.merge(action);
+        }
+
+        if self.changes.is_empty() {
+            self.deadline = None;
+        } else if self.deadline.is_none() {
+            self.deadline = Some(Instant::now() + window);
+        }
+
+        Vec::new()
+    }
+
+    /// Returns the net changes collected, removals first, and empties the batch.
+    pub(crate) fn flush(&mut self) -> Vec<EndpointAction<K>> {
+        self.deadline = None;
+
+        let mut changes: Vec<_> = self.changes.drain().map(|(_, action)| action).collect();
+        changes.sort_by_key(|action| !matches!(action, EndpointAction::Remove(_)));
+        changes
+    }
+
+    /// Merges a change into the net change of its key.
+    fn merge(&mut self, action: EndpointAction<K>) {
+        let key = match &action {
+            EndpointAction::Insert(key, _)
+            | EndpointAction::Update(key, _)
+            | EndpointAction::Remove(key) => key.clone(),
+        };
+
+        let merged = match (self.changes.remove(&key), action) {
+            // An endpoint inserted and removed again was never published
+            (Some(EndpointAction::Insert(..)), EndpointAction::Remove(_)) => None,
+
+            // An endpoint not published yet is inserted with its latest metadata
+            (Some(EndpointAction::Insert(..)), EndpointAction::Update(key, endpoint)) => {
+                Some(EndpointAction::Insert(key, endpoint))
+            }
+
+            // An endpoint removed and inserted again keeps its connection
+            (Some(EndpointAction::Remove(_)), EndpointAction::Insert(key, endpoint)) => {
+                Some(EndpointAction::Update(key, endpoint))
+            }
+
+            (_, action) => Some(action),
+        };
+
+        if let Some(merged) = merged {
+            self.changes.insert(key, merged);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use crate::endpoint::DiscoveredEndpoint;
+
+    use super::*;
+
+    fn endpoint(addr: &str) -> Box<DiscoveredEndpoint> {
+        Box::new(DiscoveredEndpoint::new(addr.parse().unwrap()))
+    }
+
+    fn insert(addr: &str) -> EndpointAction<SocketAddr> {
+        EndpointAction::Insert(addr.parse().unwrap(), endpoint(addr))
+    }
+
+    fn update(addr: &str) -> EndpointAction<SocketAddr> {
+        EndpointAction::Update(addr.parse().unwrap(), endpoint(addr))
+    }
+
+    fn remove(addr: &str) -> EndpointAction<SocketAddr> {
+        EndpointAction::Remove(addr.parse().unwrap())
+    }
+
+    fn batch() -> Batch<SocketAddr> {
+        Batch::new(Some(Duration::from_secs(1)))
+    }
+
+    #[test]
+    fn batch_without_window_passes_changes_through() {
+        let mut batch = Batch::new(None);
+
+        let changes = batch.add(vec![insert("10.0.0.1:80"), remove("10.0.0.1:80")]);
+        assert_eq!(changes.len(), 2);
+        assert!(batch.deadline().is_none());
+    }
+
+    #[test]
+    fn batch_collects_changes_until_flushed() {
+        let mut batch = batch();
+
+        assert!(batch.add(vec![insert("10.0.0.1:80")]).is_empty());
+        let deadline = batch.deadline().unwrap();
+
+        // Later changes do not extend the window
+        batch.add(vec![remove("10.0.0.2:80")]);
+        assert_eq!(batch.deadline(), Some(deadline));
+
+        assert_eq!(
+            batch.flush(),
+            vec![remove("10.0.0.2:80"), insert("10.0.0.1:80")]
+        );
+        assert!(batch.deadline().is_none());
+        assert!(batch.flush().is_empty());
+    }
+
+    #[test]
+    fn batch_cancels_insert_and_remove() {
+        let mut batch = batch();
+
+        batch.add(vec![insert("10.0.0.1:80")]);
+        batch.add(vec![update("10.0.0.1:80"), remove("10.0.0.1:80")]);
+
+        assert!(batch.deadline().is_none());
+        assert!(batch.flush().is_empty());
+    }
+
+    #[test]
+    fn batch_turns_remove_and_insert_into_update() {
+        let mut batch = batch();
+
+        batch.add(vec![remove("10.0.0.1:80")]);
+        batch.add(vec![insert("10.0.0.1:80")]);
+
+        assert_eq!(batch.flush(), vec![update("10.0.0.1:80")]);
+    }
+
+    #[test]
+    fn batch_inserts_with_latest_metadata() {
+        let mut batch = batch();
+
+        let mut terminating = DiscoveredEndpoint::new("10.0.0.1:80".parse().unwrap());
+        terminating.terminating = true;
+
+        batch.add(vec![insert("10.0.0.1:80")]);
+        batch.add(vec![EndpointAction::Update(
+            terminating.address,
+            Box::new(terminating.clone()),
+        )]);
+
+        assert_eq!(
+            batch.flush(),
+            vec![EndpointAction::Insert(
+                terminating.address,
+                Box::new(terminating)
+            )]
+        );
+    }
+
+    #[test]
+    fn batch_removes_updated_endpoint() {
+        let mut batch = batch();
+
+        batch.add(vec![update("10.0.0.1:80")]);
+        batch.add(vec![remove("10.0.0.1:80")]);
+
+        assert_eq!(batch.flush(), vec![remove("10.0.0.1:80")]);
+    }
+}
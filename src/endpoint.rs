@@ -0,0 +1,385 @@
+//! Metadata of endpoints discovered from `EndpointSlice` resources.
+
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::net::SocketAddr;
+
+use k8s_openapi::api::core::v1::ObjectReference;
+use k8s_openapi::api::discovery::v1::{Endpoint as SliceEndpoint, EndpointPort};
+
+/// Reference to the object backing an endpoint, usually a `Pod`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TargetRef {
+    /// The kind of the referenced object (e.g. `Pod`).
+    pub kind: Option<String>,
+
+    /// The namespace of the referenced object.
+    pub namespace: Option<String>,
+
+    /// The name of the referenced object.
+    pub name: Option<String>,
+
+    /// The UID of the referenced object.
+    pub uid: Option<String>,
+}
+
+impl From<&ObjectReference> for TargetRef {
+    fn from(target: &ObjectReference) -> Self {
+        Self {
+            kind: target.kind.clone(),
+            namespace: target.namespace.clone(),
+            name: target.name.clone(),
+            uid: target.uid.clone(),
+        }
+    }
+}
+
+/// An endpoint discovered from an `EndpointSlice`.
+///
+/// Passed to the build function so that each `Endpoint` can be configured using
+/// the metadata Kubernetes publishes for it, e.g. to set a per-pod TLS server name
+/// or zone-specific timeouts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DiscoveredEndpoint {
+    /// The socket address of the endpoint.
+    pub address: SocketAddr,
+
+    /// The namespace of the `EndpointSlice` the endpoint was discovered in.
+    pub namespace: Option<String>,
+
+    /// Reference to the object backing the endpoint, usually a `Pod`.
+    pub target_ref: Option<TargetRef>,
+
+    /// The name of the node hosting the endpoint.
+    pub node_name: Option<String>,
+
+    /// The zone the endpoint is located in.
+    pub zone: Option<String>,
+
+    /// The zones that should consume the endpoint, as hinted for topology-aware routing.
+    pub hints_for_zones: Vec<String>,
+
+    /// The hostname of the endpoint, if set (e.g. for `StatefulSet` pods).
+    pub hostname: Option<String>,
+
+    /// The name of the port in the `EndpointSlice`.
+    pub port_name: Option<String>,
+
+    /// The application protocol of the port (e.g. `grpc` or `kubernetes.io/h2c`).
+    pub app_protocol: Option<String>,
+
+    /// Whether the endpoint is ready to receive traffic.
+    pub ready: bool,
+
+    /// Whether the endpoint is serving, regardless of whether it is terminating.
+    pub serving: bool,
+
+    /// Whether the endpoint is terminating.
+    pub terminating: bool,
+
+    /// The relative weight of the endpoint, read from its pod if weights are
+    /// configured, or 1.
+    pub weight: u32,
+}
+
+impl DiscoveredEndpoint {
+    /// Creates a ready endpoint with the given address and no other metadata.
+    #[must_use]
+    pub fn new(address: SocketAddr) -> Self {
+        Self {
+            address,
+            namespace: None,
+            target_ref: None,
+            node_name: None,
+            zone: None,
+            hints_for_zones: Vec::new(),
+            hostname: None,
+            port_name: None,
+            app_protocol: None,
+            ready: true,
+            serving: true,
+            terminating: false,
+            weight: 1,
+        }
+    }
+
+    /// Returns the name of the backing pod, if the endpoint references one.
+    #[must_use]
+    pub fn pod_name(&self) -> Option<&str> {
+        self.target_ref
+            .as_ref()
+            .filter(|target| target.kind.as_deref() == Some("Pod"))
+            .and_then(|target| target.name.as_deref())
+    }
+
+    /// Creates an endpoint from an `EndpointSlice` entry, the slice's namespace and
+    /// the resolved port.
+    pub(crate) fn from_slice(
+        endpoint: &SliceEndpoint,
+        namespace: Option<&str>,
+        address: SocketAddr,
+        port: Option<&EndpointPort>,
+    ) -> Self {
+        let conditions = endpoint.conditions.as_ref();
+
+        // Unset conditions default to ready, serving as ready, and not terminating
+        let ready = conditions.and_then(|c| c.ready).unwrap_or(true);
+        let serving = conditions.and_then(|c| c.serving).unwrap_or(ready);
+        let terminating = conditions.and_then(|c| c.terminating).unwrap_or(false);
+
+        let hints_for_zones = endpoint
+            .hints
+            .as_ref()
+            .and_then(|hints| hints.for_zones.as_ref())
+            .map(|zones| zones.iter().map(|zone| zone.name.clone()).collect())
+            .unwrap_or_default();
+
+        Self {
+            address,
+            namespace: namespace.map(str::to_string),
+            target_ref: endpoint.target_ref.as_ref().map(TargetRef::from),
+            node_name: endpoint.node_name.clone(),
+            zone: endpoint.zone.clone(),
+            hints_for_zones,
+            hostname: endpoint.hostname.clone(),
+            port_name: port.and_then(|p| p.name.clone()),
+            app_protocol: port.and_then(|p| p.app_protocol.clone()),
+            ready,
+            serving,
+            terminating,
+            weight: 1,
+        }
+    }
+}
+
+/// Key identifying an endpoint in the balance channel.
+///
+/// Discovery is generic over the key type of the balance channel. Implemented for
+/// `SocketAddr`, which identifies endpoints by address only, for [`EndpointKey`],
+/// which also identifies the backing pod, and for [`Weighted`] keys.
+pub trait DiscoveryKey: Clone + Debug + Eq + Hash + Send + 'static {
+    /// Returns the key for a discovered endpoint.
+    fn from_endpoint(endpoint: &DiscoveredEndpoint) -> Self;
+}
+
+impl DiscoveryKey for SocketAddr {
+    fn from_endpoint(endpoint: &DiscoveredEndpoint) -> Self {
+        endpoint.address
+    }
+}
+
+/// Channel key made of an endpoint's address, namespace and the object backing it.
+///
+/// When a pod is deleted and its IP address is reused by a new pod, the key
+/// changes, so the balance channel removes the old connection and connects to
+/// the new pod instead of keeping the stale connection. The namespace allows
+/// changes to be routed to a channel per namespace, e.g. per tenant.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EndpointKey {
+    /// The socket address of the endpoint.
+    pub address: SocketAddr,
+
+    /// The namespace of the `EndpointSlice` the endpoint was discovered in.
+    pub namespace: Option<String>,
+
+    /// Reference to the object backing the endpoint, usually a `Pod`.
+    pub target_ref: Option<TargetRef>,
+}
+
+impl DiscoveryKey for EndpointKey {
+    fn from_endpoint(endpoint: &DiscoveredEndpoint) -> Self {
+        Self {
+            address: endpoint.address,
+            namespace: endpoint.namespace.clone(),
+            target_ref: endpoint.target_ref.clone(),
+        }
+    }
+}
+
+/// Channel key carrying the weight of an endpoint, as used by the
+/// [`WeightedChannel`](crate::WeightedChannel).
+///
+/// The weight is part of the key, so an endpoint whose weight changes is removed
+/// and inserted again with its new weight.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Weighted<K> {
+    /// The key identifying the endpoint.
+    pub key: K,
+
+    /// The relative weight of the endpoint.
+    pub weight: u32,
+}
+
+impl<K: DiscoveryKey> DiscoveryKey for Weighted<K> {
+    fn from_endpoint(endpoint: &DiscoveredEndpoint) -> Self {
+        Self {
+            key: K::from_endpoint(endpoint),
+            weight: endpoint.weight,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::api::discovery::v1::{EndpointConditions, EndpointHints, ForZone};
+
+    use super::*;
+
+    fn addr() -> SocketAddr {
+        "10.0.0.1:50051".parse().unwrap()
+    }
+
+    fn pod_ref(name: &str) -> ObjectReference {
+        ObjectReference {
+            kind: Some("Pod".to_string()),
+            namespace: Some("default".to_string()),
+            name: Some(name.to_string()),
+            uid: Some("uid-1".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn new_is_ready_without_metadata() {
+        let endpoint = DiscoveredEndpoint::new(addr());
+
+        assert_eq!(endpoint.address, addr());
+        assert!(endpoint.ready && endpoint.serving && !endpoint.terminating);
+        assert!(endpoint.target_ref.is_none());
+        assert!(endpoint.pod_name().is_none());
+        assert_eq!(endpoint.weight, 1);
+    }
+
+    #[test]
+    fn from_slice_copies_metadata() {
+        let slice_endpoint = SliceEndpoint {
+            addresses: vec!["10.0.0.1".to_string()],
+            target_ref: Some(pod_ref("my-pod")),
+            node_name: Some("node-1".to_string()),
+            zone: Some("us-east-1a".to_string()),
+            hostname: Some("my-host".to_string()),
+            hints: Some(EndpointHints {
+                for_zones: Some(vec![ForZone {
+                    name: "us-east-1b".to_string(),
+                }]),
+            }),
+            ..Default::default()
+        };
+
+        let port = EndpointPort {
+            name: Some("grpc".to_string()),
+            port: Some(50051),
+            app_protocol: Some("kubernetes.io/h2c".to_string()),
+            ..Default::default()
+        };
+
+        let endpoint =
+            DiscoveredEndpoint::from_slice(&slice_endpoint, Some("tenant-a"), addr(), Some(&port));
+
+        assert_eq!(endpoint.namespace.as_deref(), Some("tenant-a"));
+        assert_eq!(endpoint.pod_name(), Some("my-pod"));
+        assert_eq!(
+            endpoint.target_ref.as_ref().and_then(|t| t.uid.as_deref()),
+            Some("uid-1")
+        );
+        assert_eq!(endpoint.node_name.as_deref(), Some("node-1"));
+        assert_eq!(endpoint.zone.as_deref(), Some("us-east-1a"));
+        assert_eq!(endpoint.hints_for_zones, vec!["us-east-1b"]);
+        assert_eq!(endpoint.hostname.as_deref(), Some("my-host"));
+        assert_eq!(endpoint.port_name.as_deref(), Some("grpc"));
+        assert_eq!(endpoint.app_protocol.as_deref(), Some("kubernetes.io/h2c"));
+    }
+
+    #[test]
+    fn from_slice_without_port_has_no_port_metadata() {
+        let endpoint =
+            DiscoveredEndpoint::from_slice(&SliceEndpoint::default(), None, addr(), None);
+
+        assert!(endpoint.port_name.is_none());
+        assert!(endpoint.app_protocol.is_none());
+    }
+
+    #[test]
+    fn from_slice_reads_conditions() {
+        let slice_endpoint = SliceEndpoint {
+            conditions: Some(EndpointConditions {
+                ready: Some(false),
+                serving: Some(true),
+                terminating: Some(true),
+            }),
+            ..Default::default()
+        };
+
+        let endpoint = DiscoveredEndpoint::from_slice(&slice_endpoint, None, addr(), None);
+        assert!(!endpoint.ready && endpoint.serving && endpoint.terminating);
+    }
+
+    #[test]
+    fn from_slice_serving_defaults_to_ready() {
+        let slice_endpoint = SliceEndpoint {
+            conditions: Some(EndpointConditions {
+                ready: Some(false),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let endpoint = DiscoveredEndpoint::from_slice(&slice_endpoint, None, addr(), None);
+        assert!(!endpoint.ready && !endpoint.serving && !endpoint.terminating);
+    }
+
+    #[test]
+    fn pod_name_ignores_other_kinds() {
+        let mut endpoint = DiscoveredEndpoint::new(addr());
+        endpoint.target_ref = Some(TargetRef {
+            kind: Some("Node".to_string()),
+            name: Some("node-1".to_string()),
+            ..Default::default()
+        });
+
+        assert!(endpoint.pod_name().is_none());
+    }
+
+    // DiscoveryKey tests
+
+    #[test]
+    fn socket_addr_key_is_address() {
+        let endpoint = DiscoveredEndpoint::new(addr());
+        assert_eq!(SocketAddr::from_endpoint(&endpoint), addr());
+    }
+
+    #[test]
+    fn endpoint_key_differs_for_reused_address() {
+        let mut old = DiscoveredEndpoint::new(addr());
+        old.target_ref = Some(TargetRef::from(&pod_ref("old-pod")));
+
+        let mut new = DiscoveredEndpoint::new(addr());
+        new.target_ref = Some(TargetRef::from(&pod_ref("new-pod")));
+
+        assert_ne!(
+            EndpointKey::from_endpoint(&old),
+            EndpointKey::from_endpoint(&new)
+        );
+        assert_eq!(EndpointKey::from_endpoint(&old).address, addr());
+    }
+
+    #[test]
+    fn endpoint_key_carries_namespace() {
+        let mut endpoint = DiscoveredEndpoint::new(addr());
+        endpoint.namespace = Some("tenant-a".to_string());
+
+        let key = EndpointKey::from_endpoint(&endpoint);
+        assert_eq!(key.namespace.as_deref(), Some("tenant-a"));
+    }
+
+    #[test]
+    fn weighted_key_carries_weight() {
+        let mut endpoint = DiscoveredEndpoint::new(addr());
+        endpoint.weight = 3;
+
+        let key = Weighted::<SocketAddr>::from_endpoint(&endpoint);
+        assert_eq!(key.key, addr());
+        assert_eq!(key.weight, 3);
+    }
+}
@@ -0,0 +1,204 @@
+//! Error type for discovery failures.
+
+use std::fmt;
+
+use kube::runtime::watcher;
+use tokio::task::JoinError;
+
+use crate::retry::ErrorKind;
+
+/// Boxed error returned by user-provided endpoint build functions.
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Error returned when Kubernetes endpoint discovery fails.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The Kubernetes client could not be configured (e.g. no kubeconfig or service account).
+    Config(kube::Error),
+
+    /// The API server returned an error status.
+    Api {
+        /// The HTTP status code.
+        code: u16,
+        /// The error message returned by the API server.
+        message: String,
+    },
+
+    /// The API server denied access (HTTP 403), usually due to missing RBAC permissions.
+    Forbidden {
+        /// The error message returned by the API server.
+        message: String,
+    },
+
+    /// The watch fell out of sync with the API server and has to re-list.
+    Desync,
+
+    /// The `Service` has no port with the number given by [`Port::ServicePort`](crate::Port::ServicePort).
+    ServicePortNotFound {
+        /// The `Service` port number.
+        port: u16,
+    },
+
+    /// Traffic must stay on the client's node, but the node name is not known.
+    UnknownNode,
+
+    /// The Kubernetes client failed to reach the API server.
+    Kube(kube::Error),
+
+    /// The change channel was closed by the receiver.
+    ChannelClosed,
+
+    /// The discovery task panicked or was cancelled.
+    Task(JoinError),
+}
+
+impl Error {
+    /// Returns the broad kind of this error, as used by the [`RetryPolicy`](crate::RetryPolicy).
+    #[must_use]
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Config(_) | Self::UnknownNode => ErrorKind::Config,
+            Self::Forbidden { .. } => ErrorKind::Forbidden,
+            Self::Api { code: 404, .. } | Self::ServicePortNotFound { .. } => ErrorKind::NotFound,
+            _ => ErrorKind::Transient,
+        }
+    }
+
+    /// Creates an error from an HTTP status returned by the API server.
+    fn from_status(code: u16, message: String) -> Self {
+        match code {
+            403 => Self::Forbidden { message },
+            410 => Self::Desync,
+            _ => Self::Api { code, message },
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Config(e) => write!(f, "failed to configure Kubernetes client: {e}"),
+            Self::Api { code, message } => write!(f, "Kubernetes API error ({code}): {message}"),
+            Self::Forbidden { message } => write!(f, "Kubernetes API access forbidden: {message}"),
+            Self::Desync => f.write_str("Kubernetes watch out of sync"),
+            Self::ServicePortNotFound { port } => write!(f, "Service has no port {port}"),
+            Self::UnknownNode => f.write_str("name of the client's node is unknown"),
+            Self::Kube(e) => write!(f, "Kubernetes client error: {e}"),
+            Self::ChannelClosed => f.write_str("change channel closed"),
+            Self::Task(e) => write!(f, "discovery task failed: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Config(e) | Self::Kube(e) => Some(e),
+            Self::Task(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<kube::Error> for Error {
+    fn from(err: kube::Error) -> Self {
+        match err {
+            kube::Error::Api(response) => Self::from_status(response.code, response.message),
+            kube::Error::InferConfig(_) | kube::Error::Auth(_) => Self::Config(err),
+            _ => Self::Kube(err),
+        }
+    }
+}
+
+impl From<watcher::Error> for Error {
+    fn from(err: watcher::Error) -> Self {
+        match err {
+            watcher::Error::InitialListFailed(e)
+            | watcher::Error::WatchStartFailed(e)
+            | watcher::Error::WatchFailed(e) => e.into(),
+            watcher::Error::WatchError(response) => {
+                Self::from_status(response.code, response.message)
+            }
+            watcher::Error::NoResourceVersion => Self::Desync,
+        }
+    }
+}
+
+/// Result type for discovery operations.
+pub(crate) type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Status classification tests
+
+    #[test]
+    fn status_forbidden_is_forbidden() {
+        let err = Error::from_status(403, "denied".to_string());
+
+        assert!(matches!(err, Error::Forbidden { ref message } if message == "denied"));
+        assert_eq!(err.kind(), ErrorKind::Forbidden);
+    }
+
+    #[test]
+    fn status_not_found_is_not_found() {
+        let err = Error::from_status(404, "missing".to_string());
+
+        assert!(matches!(err, Error::Api { code: 404, .. }));
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn status_gone_is_desync() {
+        let err = Error::from_status(410, "too old".to_string());
+
+        assert!(matches!(err, Error::Desync));
+        assert_eq!(err.kind(), ErrorKind::Transient);
+    }
+
+    #[test]
+    fn status_other_is_transient() {
+        for code in [429, 500, 503] {
+            let err = Error::from_status(code, String::new());
+
+            assert!(matches!(err, Error::Api { code: c, .. } if c == code));
+            assert_eq!(err.kind(), ErrorKind::Transient);
+        }
+    }
+
+    #[test]
+    fn watcher_no_resource_version_is_desync() {
+        let err = Error::from(watcher::Error::NoResourceVersion);
+        assert!(matches!(err, Error::Desync));
+    }
+
+    // Kind tests
+
+    #[test]
+    fn channel_closed_is_transient() {
+        assert_eq!(Error::ChannelClosed.kind(), ErrorKind::Transient);
+    }
+
+    #[test]
+    fn service_port_not_found_is_not_found() {
+        let err = Error::ServicePortNotFound { port: 80 };
+
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert_eq!(err.to_string(), "Service has no port 80");
+    }
+
+    #[test]
+    fn unknown_node_is_config() {
+        assert_eq!(Error::UnknownNode.kind(), ErrorKind::Config);
+    }
+
+    // Display tests
+
+    #[test]
+    fn display_includes_status_code() {
+        let err = Error::from_status(500, "internal".to_string());
+        assert_eq!(err.to_string(), "Kubernetes API error (500): internal");
+    }
+}
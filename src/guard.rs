@@ -0,0 +1,346 @@
+//! Protection against mass endpoint removal.
+//!
+//! A bad API response, a selector change or a controller bug may momentarily
+//! empty the watched slices. Like Envoy's panic mode, the panic threshold keeps
+//! endpoints in the channel rather than trusting such a sudden drop: removals
+//! that would cross the threshold are held back until they are confirmed by
+//! persisting for a while, or the endpoints come back.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+use crate::endpoint::DiscoveryKey;
+use crate::k8s::EndpointAction;
+
+/// Threshold below which endpoint removals are held back.
+///
+/// By default, no removals are held back. Set a minimum number of endpoints, a
+/// maximum share of endpoints removed within a window, or both.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PanicThreshold {
+    /// Minimum number of endpoints kept in the channel.
+    pub min_endpoints: Option<usize>,
+
+    /// Maximum percentage of the endpoints removed within the window.
+    pub max_removed_percent: Option<u8>,
+
+    /// Window over which removals are counted against `max_removed_percent`.
+    pub window: Duration,
+
+    /// How long a removal must persist before it proceeds despite the threshold,
+    /// so genuine scale-downs complete.
+    pub confirmation: Duration,
+}
+
+impl Default for PanicThreshold {
+    fn default() -> Self {
+        Self {
+            min_endpoints: None,
+            max_removed_percent: None,
+            window: Duration::from_secs(30),
+            confirmation: Duration::from_secs(30),
+        }
+    }
+}
+
+impl PanicThreshold {
+    /// Sets the minimum number of endpoints kept in the channel.
+    #[must_use]
+    pub fn min_endpoints(mut self, min_endpoints: usize) -> Self {
+        self.min_endpoints = Some(min_endpoints);
+        self
+    }
+
+    /// Sets the maximum percentage of the endpoints removed within the window.
+    #[must_use]
+    pub fn max_removed_percent(mut self, percent: u8) -> Self {
+        self.max_removed_percent = Some(percent.min(100));
+        self
+    }
+
+    /// Sets the window over which removals are counted.
+    #[must_use]
+    pub fn window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Sets how long a removal must persist before it proceeds despite the threshold.
+    #[must_use]
+    pub fn confirmation(mut self, confirmation: Duration) -> Self {
+        self.confirmation = confirmation;
+        self
+    }
+}
+
+/// Holds back removals crossing the panic threshold.
+#[derive(Debug)]
+pub(crate) struct PanicGuard<K> {
+    /// The threshold, or `None` to let all removals through.
+    threshold: Option<PanicThreshold>,
+
+    /// Number of endpoints in the channel, including held back ones.
+    published: usize,
+
+    /// Times of the removals let through within the window.
+    recent: VecDeque<Instant>,
+
+    /// Removals held back, oldest first, with the time they were held back.
+    held: VecDeque<(K, Instant)>,
+}
+
+impl<K: DiscoveryKey> PanicGuard<K> {
+    /// Creates a guard for the given threshold.
+    pub(crate) fn new(threshold: Option<PanicThreshold>) -> Self {
+        Self {
+            threshold,
+            published: 0,
+            recent: VecDeque::new(),
+            held: VecDeque::new(),
+        }
+    }
+
+    /// Returns when held back removals may proceed, if there are any.
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        let threshold = self.threshold.as_ref()?;
+        let (_, since) = self.held.front()?;
+        let confirmed = *since + threshold.confirmation;
+
+        // Removals leaving the window make room for held back ones
+        let expired = self
+            .recent
+            .front()
+            .map(|removed| *removed + threshold.window);
+        Some(expired.map_or(confirmed, |expired| expired.min(confirmed)))
+    }
+
+    /// Applies changes and returns those to publish, holding back removals that
+    /// cross the threshold and releasing those that may proceed.
+    pub(crate) fn apply(
+        &mut self,
+        actions: Vec<EndpointAction<K>>,
+        now: Instant,
+    ) -> Vec<EndpointAction<K>> {
+        let Some(threshold) = self.threshold.clone() else {
+            return actions;
+        };
+
+        let mut changes = Vec::new();
+        let mut removals = 0;
+        for action in actions {
+            match action {
+                EndpointAction::Remove(key) => {
+                    removals += 1;
+                    self.held.push_back((key, now));
+                }
+
+                EndpointAction::Insert(key, endpoint) => {
+                    // An endpoint coming back was never removed from the channel
+                    if let Some(index) = self.held.iter().position(|(held, _)| *held == key) {
+                        self.held.remove(index);
+                        changes.push(EndpointAction::Update(key, endpoint));
+                    } else {
+                        self.published += 1;
+                        changes.push(EndpointAction::Insert(key, endpoint));
+                    }
+                }
+
+                EndpointAction::Update(..) => changes.push(action),
+            }
+        }
+
+        self.release(&threshold, now, &mut changes);
+
+        // Removals are released oldest first, so the removals of this call are the
+        // last ones held; warn once when they are held, not on every later call
+        let (held, published) = (self.held.len(), self.published);
+        if held.min(removals) > 0 {
+            warn!(
+                "panic threshold reached, holding back removal of {held} of {published} endpoints"
+            );
+        } else if held > 0 {
+            debug!("still holding back removal of {held} of {published} endpoints");
+        }
+
+        changes
+    }
+
+    /// Releases the held back removals that may proceed.
+    fn release(
+        &mut self,
+        threshold: &PanicThreshold,
+        now: Instant,
+        changes: &mut Vec<EndpointAction<K>>,
+    ) {
+        while self
+            .recent
+            .front()
+            .is_some_and(|removed| now.duration_since(*removed) >= threshold.window)
+        {
+            self.recent.pop_front();
+        }
+
+        let mut allowed = self.allowed(threshold);
+        while let Some((_, since)) = self.held.front() {
+            if allowed > 0 {
+                allowed -= 1;
+            } else if now.duration_since(*since) >= threshold.confirmation {
+                warn!("removal persisted, proceeding despite the panic threshold");
+            } else {
+                break;
+            }
+
+            let (key, _) = self.held.pop_front().expect("held removal");
+            self.published = self.published.saturating_sub(1);
+            self.recent.push_back(now);
+            changes.push(EndpointAction::Remove(key));
+        }
+    }
+
+    /// Returns how many endpoints may be removed without crossing the threshold.
+    fn allowed(&self, threshold: &PanicThreshold) -> usize {
+        let by_count = threshold
+            .min_endpoints
+            .map_or(usize::MAX, |min| self.published.saturating_sub(min));
+
+        // The share is relative to the endpoints published before the window
+        let by_share = threshold.max_removed_percent.map_or(usize::MAX, |percent| {
+            let total = self.published + self.recent.len();
+            (total * usize::from(percent) / 100).saturating_sub(self.recent.len())
+        });
+
+        by_count.min(by_share)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use crate::endpoint::DiscoveredEndpoint;
+
+    use super::*;
+
+    fn insert(addr: &str) -> EndpointAction<SocketAddr> {
+        let endpoint = DiscoveredEndpoint::new(addr.parse().unwrap());
+        EndpointAction::Insert(endpoint.address, Box::new(endpoint))
+    }
+
+    fn remove(addr: &str) -> EndpointAction<SocketAddr> {
+        EndpointAction::Remove(addr.parse().unwrap())
+    }
+
+    fn addr(i: usize) -> String {
+        format!("10.0.0.{i}:80")
+    }
+
+    // Creates a guard with the given number of published endpoints
+    fn guard(threshold: PanicThreshold, endpoints: usize, now: Instant) -> PanicGuard<SocketAddr> {
+        let mut guard = PanicGuard::new(Some(threshold));
+        guard.apply((1..=endpoints).map(|i| insert(&addr(i))).collect(), now);
+        guard
+    }
+
+    fn removes(range: std::ops::RangeInclusive<usize>) -> Vec<EndpointAction<SocketAddr>> {
+        range.map(|i| remove(&addr(i))).collect()
+    }
+
+    // PanicThreshold tests
+
+    #[test]
+    fn threshold_defaults_hold_nothing() {
+        let now = Instant::now();
+        let mut guard = guard(PanicThreshold::default(), 3, now);
+
+        assert_eq!(guard.apply(removes(1..=3), now).len(), 3);
+        assert_eq!(guard.held.len(), 0);
+    }
+
+    #[test]
+    fn threshold_caps_percent() {
+        let threshold = PanicThreshold::default().max_removed_percent(150);
+        assert_eq!(threshold.max_removed_percent, Some(100));
+    }
+
+    // PanicGuard tests
+
+    #[test]
+    fn guard_without_threshold_passes_through() {
+        let mut guard = PanicGuard::<SocketAddr>::new(None);
+
+        assert_eq!(guard.apply(removes(1..=3), Instant::now()).len(), 3);
+        assert!(guard.deadline().is_none());
+    }
+
+    #[test]
+    fn guard_keeps_min_endpoints() {
+        let now = Instant::now();
+        let mut guard = guard(PanicThreshold::default().min_endpoints(2), 5, now);
+
+        let changes = guard.apply(removes(1..=5), now);
+        assert_eq!(changes, removes(1..=3));
+        assert_eq!(guard.held.len(), 2);
+    }
+
+    #[test]
+    fn guard_limits_share_removed_within_window() {
+        let now = Instant::now();
+        let threshold = PanicThreshold::default()
+            .max_removed_percent(50)
+            .window(Duration::from_secs(10))
+            .confirmation(Duration::from_secs(45));
+        let mut guard = guard(threshold, 10, now);
+
+        assert_eq!(guard.apply(removes(1..=3), now).len(), 3);
+        assert_eq!(guard.apply(removes(4..=8), now), removes(4..=5));
+        assert_eq!(guard.held.len(), 3);
+
+        // Once the earlier removals leave the window, half of the rest may go
+        let later = now + Duration::from_secs(10);
+        assert_eq!(guard.deadline(), Some(later));
+        assert_eq!(guard.apply(Vec::new(), later), removes(6..=7));
+    }
+
+    #[test]
+    fn guard_confirms_persisting_removals() {
+        let now = Instant::now();
+        let threshold = PanicThreshold::default()
+            .min_endpoints(3)
+            .confirmation(Duration::from_secs(30));
+        let mut guard = guard(threshold, 3, now);
+
+        assert!(guard.apply(removes(1..=3), now).is_empty());
+        assert_eq!(guard.deadline(), Some(now + Duration::from_secs(30)));
+
+        let later = now + Duration::from_secs(30);
+        assert_eq!(guard.apply(Vec::new(), later), removes(1..=3));
+        assert_eq!(guard.held.len(), 0);
+    }
+
+    #[test]
+    fn guard_keeps_connection_of_returning_endpoint() {
+        let now = Instant::now();
+        let mut guard = guard(PanicThreshold::default().min_endpoints(1), 1, now);
+
+        assert!(guard.apply(removes(1..=1), now).is_empty());
+
+        let changes = guard.apply(vec![insert(&addr(1))], now);
+        assert!(matches!(changes.as_slice(), [EndpointAction::Update(..)]));
+        assert_eq!(guard.held.len(), 0);
+    }
+
+    #[test]
+    fn guard_releases_held_removals_as_endpoints_come() {
+        let now = Instant::now();
+        let mut guard = guard(PanicThreshold::default().min_endpoints(2), 2, now);
+
+        assert!(guard.apply(removes(1..=1), now).is_empty());
+
+        // A replacement endpoint makes room for the held back removal
+        let changes = guard.apply(vec![insert(&addr(3))], now);
+        assert_eq!(changes, vec![insert(&addr(3)), remove(&addr(1))]);
+    }
+}
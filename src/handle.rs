@@ -0,0 +1,448 @@
+//! Handle for controlling and observing a running discovery task.
+
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::error;
+
+use crate::error::{Error, Result};
+
+/// Lifecycle status of a discovery task.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiscoveryStatus {
+    /// Discovery has started but the initial list of endpoints is not complete yet.
+    Starting,
+    /// The endpoint set is synchronized with the Kubernetes API.
+    Synced,
+    /// Discovery is retrying after a failure; the last known endpoints are kept.
+    Degraded,
+    /// Discovery has stopped and no more changes will be sent.
+    Stopped,
+}
+
+/// Reason why discovery is not ready yet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NotReady {
+    /// The initial list of `EndpointSlice` resources has not completed yet.
+    Syncing,
+    /// No `EndpointSlice` resources match the configured service.
+    NoSlices,
+    /// None of the matching `EndpointSlice` resources expose the configured port.
+    PortNotFound,
+    /// The matching `EndpointSlice` resources contain no ready endpoints.
+    NoReadyEndpoints,
+    /// Fewer endpoints than the configured minimum are ready.
+    TooFewEndpoints {
+        /// Number of endpoints sent to the channel.
+        found: usize,
+        /// Configured minimum number of endpoints.
+        required: usize,
+    },
+    /// Discovery stopped before becoming ready.
+    Stopped,
+}
+
+impl fmt::Display for NotReady {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Syncing => f.write_str("initial endpoint list not complete"),
+            Self::NoSlices => f.write_str("no matching EndpointSlices"),
+            Self::PortNotFound => f.write_str("port not found in any EndpointSlice"),
+            Self::NoReadyEndpoints => f.write_str("no ready endpoints"),
+            Self::TooFewEndpoints { found, required } => {
+                write!(f, "{found} ready endpoints, {required} required")
+            }
+            Self::Stopped => f.write_str("discovery stopped"),
+        }
+    }
+}
+
+impl std::error::Error for NotReady {}
+
+/// Snapshot of discovery progress shared between the task and its handle.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct State {
+    /// Current lifecycle status.
+    pub(crate) status: DiscoveryStatus,
+
+    /// Whether the initial list of `EndpointSlice` resources has completed.
+    pub(crate) initialized: bool,
+
+    /// Number of tracked `EndpointSlice` resources.
+    pub(crate) slices: usize,
+
+    /// Number of tracked `EndpointSlice` resources exposing the configured port.
+    pub(crate) slices_with_port: usize,
+
+    /// Number of endpoints sent to the channel.
+    pub(crate) endpoints: usize,
+
+    /// Minimum number of endpoints required for readiness.
+    pub(crate) min_endpoints: usize,
+
+    /// Total number of failed endpoint builds.
+    pub(crate) build_failures: u64,
+}
+
+impl State {
+    /// Creates the initial state.
+    fn new(min_endpoints: usize) -> Self {
+        Self {
+            status: DiscoveryStatus::Starting,
+            initialized: false,
+            slices: 0,
+            slices_with_port: 0,
+            endpoints: 0,
+            min_endpoints,
+            build_failures: 0,
+        }
+    }
+
+    /// Returns whether discovery is ready, or the reason why it is not.
+    fn readiness(&self) -> std::result::Result<(), NotReady> {
+        if self.initialized && self.endpoints >= self.min_endpoints {
+            return Ok(());
+        }
+
+        Err(if self.status == DiscoveryStatus::Stopped {
+            NotReady::Stopped
+        } else if !self.initialized {
+            NotReady::Syncing
+        } else if self.slices == 0 {
+            NotReady::NoSlices
+        } else if self.slices_with_port == 0 {
+            NotReady::PortNotFound
+        } else if self.endpoints == 0 {
+            NotReady::NoReadyEndpoints
+        } else {
+            NotReady::TooFewEndpoints {
+                found: self.endpoints,
+                required: self.min_endpoints,
+            }
+        })
+    }
+}
+
+/// Applies a modification to the shared state, notifying the handle only on change.
+pub(crate) fn update(state: &watch::Sender<State>, f: impl FnOnce(&mut State)) {
+    state.send_if_modified(|current| {
+        let before = current.clone();
+        f(current);
+        *current != before
+    });
+}
+
+/// Handle to a running discovery task.
+///
+/// Dropping the handle detaches the task, which keeps running until the
+/// channel is closed or a fatal error occurs.
+#[derive(Debug)]
+pub struct DiscoveryHandle {
+    state: watch::Receiver<State>,
+    shutdown: watch::Sender<bool>,
+    task: JoinHandle<Result<()>>,
+}
+
+impl DiscoveryHandle {
+    /// Returns the current status of the discovery task.
+    #[must_use]
+    pub fn status(&self) -> DiscoveryStatus {
+        self.state.borrow().status
+    }
+
+    /// Returns the total number of endpoints that failed to build.
+    ///
+    /// Failed builds are retried on the next endpoint update.
+    #[must_use]
+    pub fn build_failures(&self) -> u64 {
+        self.state.borrow().build_failures
+    }
+
+    /// Waits until the initial list of endpoints has been sent to the channel.
+    ///
+    /// Resolves once the watcher has completed its initial list and at least
+    /// [`min_endpoints`](crate::DiscoveryConfig::min_endpoints) endpoints were sent.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NotReady::Stopped`] if discovery stops before becoming ready.
+    pub async fn ready(&self) -> std::result::Result<(), NotReady> {
+        let mut state = self.state.clone();
+        let result = state
+            .wait_for(|s| s.readiness().is_ok() || s.status == DiscoveryStatus::Stopped)
+            .await;
+
+        match result {
+            Ok(s) => s.readiness(),
+            Err(_) => Err(NotReady::Stopped),
+        }
+    }
+
+    /// Waits until discovery is ready, giving up after the given timeout.
+    ///
+    /// # Errors
+    ///
+    /// Returns the reason why discovery is not ready if the timeout elapses
+    /// or discovery stops first.
+    pub async fn ready_timeout(&self, timeout: Duration) -> std::result::Result<(), NotReady> {
+        match tokio::time::timeout(timeout, self.ready()).await {
+            Ok(result) => result,
+            Err(_) => self.state.borrow().readiness(),
+        }
+    }
+
+    /// Signals the discovery task to stop.
+    ///
+    /// The endpoints already sent to the channel are left in place.
+    /// Use [`join`](Self::join) to wait for the task to finish.
+    pub fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// Waits for the discovery task to finish.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error that terminated discovery, if it stopped due to a fatal failure.
+    pub async fn join(self) -> Result<()> {
+        self.task.await.map_err(Error::Task)?
+    }
+}
+
+/// Spawns a discovery task and returns a handle to it.
+///
+/// The task receives a sender to report its progress and is stopped when
+/// [`DiscoveryHandle::shutdown`] is called.
+pub(crate) fn spawn<F, Fut>(min_endpoints: usize, run: F) -> DiscoveryHandle
+where
+    F: FnOnce(watch::Sender<State>) -> Fut,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let (state_tx, state) = watch::channel(State::new(min_endpoints));
+    let (shutdown, mut shutdown_rx) = watch::channel(false);
+    let fut = run(state_tx.clone());
+
+    let task = tokio::spawn(async move {
+        let result = tokio::select! {
+            result = fut => result,
+            // A closed channel means the handle was dropped, which detaches the task
+            Ok(_) = shutdown_rx.wait_for(|&stop| stop) => Ok(()),
+        };
+
+        match &result {
+            Err(Error::ChannelClosed) | Ok(()) => {}
+            Err(e) => error!("Kubernetes endpoint watcher failed: {e}"),
+        }
+
+        update(&state_tx, |s| s.status = DiscoveryStatus::Stopped);
+        result
+    });
+
+    DiscoveryHandle {
+        state,
+        shutdown,
+        task,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn status_starts_as_starting() {
+        let handle = spawn(1, |_| future::pending());
+        assert_eq!(handle.status(), DiscoveryStatus::Starting);
+
+        handle.shutdown();
+        handle.join().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn status_reflects_task_updates() {
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let handle = spawn(1, |state| async move {
+            update(&state, |s| s.status = DiscoveryStatus::Synced);
+            let _ = rx.await;
+            Ok(())
+        });
+
+        let mut state = handle.state.clone();
+        state
+            .wait_for(|s| s.status == DiscoveryStatus::Synced)
+            .await
+            .unwrap();
+
+        tx.send(()).unwrap();
+        handle.join().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn shutdown_stops_task() {
+        let handle = spawn(1, |_| future::pending());
+        handle.shutdown();
+
+        let mut state = handle.state.clone();
+        state
+            .wait_for(|s| s.status == DiscoveryStatus::Stopped)
+            .await
+            .unwrap();
+
+        assert!(handle.join().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn dropping_handle_detaches_task() {
+        let (go_tx, go_rx) = tokio::sync::oneshot::channel::<()>();
+        let (done_tx, done_rx) = tokio::sync::oneshot::channel::<()>();
+        let handle = spawn(1, |_| async move {
+            let _ = go_rx.await;
+            let _ = done_tx.send(());
+            Ok(())
+        });
+
+        drop(handle);
+        tokio::task::yield_now().await;
+
+        go_tx.send(()).unwrap();
+        done_rx.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn join_yields_terminal_error() {
+        let handle = spawn(1, |_| async { Err(Error::ChannelClosed) });
+        let err = handle.join().await.unwrap_err();
+
+        assert!(matches!(err, Error::ChannelClosed));
+    }
+
+    #[tokio::test]
+    async fn status_is_stopped_after_task_ends() {
+        let handle = spawn(1, |_| async { Ok(()) });
+        let mut state = handle.state.clone();
+
+        state
+            .wait_for(|s| s.status == DiscoveryStatus::Stopped)
+            .await
+            .unwrap();
+
+        assert_eq!(handle.status(), DiscoveryStatus::Stopped);
+    }
+
+    // Readiness tests
+
+    fn synced(slices: usize, slices_with_port: usize, endpoints: usize) -> State {
+        State {
+            status: DiscoveryStatus::Synced,
+            initialized: true,
+            slices,
+            slices_with_port,
+            endpoints,
+            min_endpoints: 2,
+            build_failures: 0,
+        }
+    }
+
+    #[test]
+    fn readiness_requires_initial_list() {
+        let state = State::new(1);
+        assert_eq!(state.readiness(), Err(NotReady::Syncing));
+    }
+
+    #[test]
+    fn readiness_reports_missing_slices() {
+        assert_eq!(synced(0, 0, 0).readiness(), Err(NotReady::NoSlices));
+    }
+
+    #[test]
+    fn readiness_reports_missing_port() {
+        assert_eq!(synced(2, 0, 0).readiness(), Err(NotReady::PortNotFound));
+    }
+
+    #[test]
+    fn readiness_reports_no_ready_endpoints() {
+        assert_eq!(synced(2, 1, 0).readiness(), Err(NotReady::NoReadyEndpoints));
+    }
+
+    #[test]
+    fn readiness_reports_too_few_endpoints() {
+        assert_eq!(
+            synced(1, 1, 1).readiness(),
+            Err(NotReady::TooFewEndpoints {
+                found: 1,
+                required: 2
+            })
+        );
+    }
+
+    #[test]
+    fn readiness_ok_with_enough_endpoints() {
+        assert_eq!(synced(1, 1, 2).readiness(), Ok(()));
+    }
+
+    #[test]
+    fn readiness_zero_minimum_ready_without_endpoints() {
+        let state = State {
+            min_endpoints: 0,
+            ..synced(0, 0, 0)
+        };
+
+        assert_eq!(state.readiness(), Ok(()));
+        assert_eq!(
+            State::new(0).readiness(),
+            Err(NotReady::Syncing),
+            "the initial list is still required"
+        );
+    }
+
+    #[tokio::test]
+    async fn ready_resolves_once_endpoints_are_sent() {
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let handle = spawn(1, |state| async move {
+            let _ = rx.await;
+            update(&state, |s| {
+                s.initialized = true;
+                s.slices = 1;
+                s.slices_with_port = 1;
+                s.endpoints = 1;
+            });
+
+            future::pending().await
+        });
+
+        tx.send(()).unwrap();
+        assert_eq!(handle.ready().await, Ok(()));
+
+        handle.shutdown();
+        handle.join().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn ready_fails_when_discovery_stops() {
+        let handle = spawn(1, |_| async { Err(Error::ChannelClosed) });
+        assert_eq!(handle.ready().await, Err(NotReady::Stopped));
+    }
+
+    #[tokio::test]
+    async fn ready_timeout_reports_reason() {
+        let handle = spawn(1, |state| async move {
+            update(&state, |s| {
+                s.initialized = true;
+                s.slices = 1;
+            });
+
+            future::pending().await
+        });
+
+        let result = handle.ready_timeout(Duration::from_millis(50)).await;
+        assert_eq!(result, Err(NotReady::PortNotFound));
+
+        handle.shutdown();
+        handle.join().await.unwrap();
+    }
+}
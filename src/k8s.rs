@@ -22,10 +22,10 @@
 //! // Create your own balance channel
 //! let (channel, tx) = Channel::balance_channel::<SocketAddr>(1024);
 //!
-//! // Start discovery - build function returns Endpoint for each address
+//! // Start discovery - build function returns Endpoint for each discovered endpoint
 //! let config = DiscoveryConfig::new("my-grpc-service", 50051);
-//! discover(config, tx, |addr| {
-//!     Endpoint::from_shared(format!("http://{addr}"))
+//! discover(config, tx, |endpoint| {
+//!     Endpoint::from_shared(format!("http://{}", endpoint.address))
 //!         .unwrap()
 //!         .connect_timeout(Duration::from_secs(5))
 //! });
@@ -34,32 +34,62 @@
 //! let client = MyServiceClient::new(channel);
 //! ```
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::fmt;
+use std::future::{self, Future};
 use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::Duration;
 
-use futures::TryStreamExt;
-use k8s_openapi::api::discovery::v1::EndpointSlice;
-use kube::runtime::WatchStreamExt;
+use futures::stream::{self, BoxStream};
+use futures::{StreamExt, TryStreamExt};
+use k8s_openapi::api::core::v1::{Pod, Service};
+use k8s_openapi::api::discovery::v1::{EndpointPort, EndpointSlice};
 use kube::runtime::watcher::{self, Config as WatcherConfig, Event};
 use kube::{Api, Client};
 use tokio::sync::mpsc::Sender;
+use tokio::sync::watch;
+use tokio::time::Instant;
 use tonic::transport::Endpoint;
 use tonic::transport::channel::Change;
-use tracing::{debug, error, warn};
-
-/// Error type for discovery failures.
-type Error = Box<dyn std::error::Error + Send + Sync>;
-
-/// Result type for discovery operations.
-type Result<T> = std::result::Result<T, Error>;
+use tracing::{debug, warn};
+
+use crate::batch::Batch;
+use crate::endpoint::{DiscoveredEndpoint, DiscoveryKey};
+use crate::error::{BoxError, Error, Result};
+use crate::guard::{PanicGuard, PanicThreshold};
+use crate::handle::{self, DiscoveryHandle, DiscoveryStatus, State};
+use crate::publisher::Publisher;
+use crate::retry::{ErrorKind, RetryPolicy};
+use crate::select::{ReadinessPolicy, Selection};
+use crate::subset::{Subset, Subsetter};
+use crate::topology::{NodeLocal, NodePreference, ZoneAware, ZonePreference};
+use crate::weight::{SharedWeights, WeightSource, Weights};
 
 /// Port specification for the gRPC service.
+///
+/// Ports are resolved separately for each `EndpointSlice`. Endpoints of slices
+/// where the port cannot be resolved are not discovered.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Port {
-    /// A numeric port number.
+    /// A numeric port number. The slice must publish it as a TCP port, unless it
+    /// lists no port numbers at all (e.g. for headless services without ports).
     Number(u16),
     /// A named port (resolved from `EndpointSlice`).
     Name(String),
+    /// The first port with the given `appProtocol` (e.g. `grpc` or `kubernetes.io/h2c`).
+    AppProtocol(String),
+    /// The first port with the given protocol (`TCP`, `UDP` or `SCTP`).
+    Protocol(String),
+    /// The only TCP port of the slice. Not resolved if the slice has several TCP ports.
+    OnlyTcp,
+    /// A port number of the `Service`, mapped to its target port.
+    ///
+    /// The `Service` is read once when discovery starts, which requires permission
+    /// to `get` it. Changes to the target port are still picked up, as the matching
+    /// `EndpointSlice` port is looked up by the name of the `Service` port.
+    ServicePort(u16),
 }
 
 impl From<u16> for Port {
@@ -80,8 +110,139 @@ impl From<String> for Port {
     }
 }
 
+/// Namespaces watched in cluster-wide discovery.
+///
+/// By default, every namespace is watched. See [`DiscoveryConfig::all_namespaces`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NamespaceFilter {
+    /// Namespaces to watch. If empty, every namespace that is not denied is watched.
+    pub allow: HashSet<String>,
+
+    /// Namespaces never watched, even if allowed.
+    pub deny: HashSet<String>,
+}
+
+impl NamespaceFilter {
+    /// Adds a namespace to watch, restricting discovery to the allowed namespaces.
+    #[must_use]
+    pub fn allow(mut self, namespace: impl Into<String>) -> Self {
+        self.allow.insert(namespace.into());
+        self
+    }
+
+    /// Adds a namespace never to watch.
+    #[must_use]
+    pub fn deny(mut self, namespace: impl Into<String>) -> Self {
+        self.deny.insert(namespace.into());
+        self
+    }
+
+    /// Returns whether a namespace passes the filter.
+    fn allows(&self, namespace: &str) -> bool {
+        (self.allow.is_empty() || self.allow.contains(namespace)) && !self.deny.contains(namespace)
+    }
+
+    /// Returns whether an event concerns a slice in an allowed namespace.
+    ///
+    /// Events without a slice, such as the start of a re-list, always pass.
+    fn admits(&self, event: &Event<EndpointSlice>) -> bool {
+        match event {
+            Event::Apply(slice) | Event::InitApply(slice) | Event::Delete(slice) => {
+                self.allows(slice.metadata.namespace.as_deref().unwrap_or_default())
+            }
+            Event::Init | Event::InitDone => true,
+        }
+    }
+}
+
+/// A Service whose endpoints are merged into the same channel.
+///
+/// See [`DiscoveryConfig::add_service`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServiceRef {
+    /// The Kubernetes service name to watch.
+    pub name: String,
+
+    /// The Kubernetes namespace where the service is deployed.
+    /// If `None`, uses the namespace of the [`DiscoveryConfig`].
+    pub namespace: Option<String>,
+
+    /// The port for the gRPC service.
+    pub port: Port,
+
+    /// Label selector for the `EndpointSlice` resources to watch.
+    /// If `None`, selects the slices of the Service by its name.
+    pub label_selector: Option<String>,
+
+    /// Field selector for the `EndpointSlice` resources to watch.
+    pub field_selector: Option<String>,
+
+    /// If set, watches the Service's `EndpointSlice` resources in all namespaces
+    /// passing the filter instead of a single namespace.
+    pub all_namespaces: Option<NamespaceFilter>,
+}
+
+impl ServiceRef {
+    /// Creates a reference to a Service in the namespace of the [`DiscoveryConfig`].
+    #[must_use]
+    pub fn new(name: impl Into<String>, port: impl Into<Port>) -> Self {
+        Self {
+            name: name.into(),
+            namespace: None,
+            port: port.into(),
+            label_selector: None,
+            field_selector: None,
+            all_namespaces: None,
+        }
+    }
+
+    /// Sets an explicit namespace for the service.
+    #[must_use]
+    pub fn namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    /// Sets a label selector replacing the default `kubernetes.io/service-name` selector.
+    #[must_use]
+    pub fn label_selector(mut self, selector: impl Into<String>) -> Self {
+        self.label_selector = Some(selector.into());
+        self
+    }
+
+    /// Sets a field selector for the `EndpointSlice` resources to watch.
+    #[must_use]
+    pub fn field_selector(mut self, selector: impl Into<String>) -> Self {
+        self.field_selector = Some(selector.into());
+        self
+    }
+
+    /// Watches the Service in all namespaces passing the filter.
+    ///
+    /// See [`DiscoveryConfig::all_namespaces`].
+    #[must_use]
+    pub fn all_namespaces(mut self, filter: NamespaceFilter) -> Self {
+        self.all_namespaces = Some(filter);
+        self
+    }
+
+    /// Returns the watcher configuration selecting the `EndpointSlice` resources.
+    fn watcher_config(&self) -> WatcherConfig {
+        let config = match &self.label_selector {
+            Some(selector) => WatcherConfig::default().labels(selector),
+            None => WatcherConfig::default()
+                .labels(&format!("kubernetes.io/service-name={}", self.name)),
+        };
+
+        match &self.field_selector {
+            Some(selector) => config.fields(selector),
+            None => config,
+        }
+    }
+}
+
 /// Configuration for Kubernetes endpoint discovery.
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct DiscoveryConfig {
     /// The Kubernetes service name to watch.
     pub service_name: String,
@@ -90,8 +251,54 @@ pub struct DiscoveryConfig {
     /// If `None`, uses the current namespace from the kube client.
     pub namespace: Option<String>,
 
+    /// The Kubernetes client used to watch endpoints.
+    /// If `None`, a client is inferred from the environment (in-cluster or kubeconfig).
+    pub client: Option<Client>,
+
     /// The port for the gRPC service (number or name).
     pub port: Port,
+
+    /// How client construction and watch failures are retried.
+    pub retry: RetryPolicy,
+
+    /// Minimum number of endpoints required before discovery is considered ready.
+    /// See [`DiscoveryHandle::ready`].
+    pub min_endpoints: usize,
+
+    /// Which endpoints are sent to the channel, based on their conditions.
+    pub readiness: ReadinessPolicy,
+
+    /// Additional Services whose endpoints are merged into the same channel.
+    pub additional_services: Vec<ServiceRef>,
+
+    /// Label selector for the `EndpointSlice` resources to watch.
+    /// If `None`, selects the slices of the service by its name.
+    pub label_selector: Option<String>,
+
+    /// Field selector for the `EndpointSlice` resources to watch.
+    pub field_selector: Option<String>,
+
+    /// If set, watches the service's `EndpointSlice` resources in all namespaces
+    /// passing the filter instead of a single namespace.
+    pub all_namespaces: Option<NamespaceFilter>,
+
+    /// If set, prefers endpoints in the client's own zone.
+    pub zone_aware: Option<ZoneAware>,
+
+    /// If set, prefers or requires endpoints on the client's own node.
+    pub node_local: Option<NodeLocal>,
+
+    /// If set, reads the weight of each endpoint from its pod.
+    pub weights: Option<WeightSource>,
+
+    /// If set, sends only a bounded subset of the endpoints to the channel.
+    pub subset: Option<Subset>,
+
+    /// If set, collects endpoint changes for this long and sends their net effect.
+    pub settle_window: Option<Duration>,
+
+    /// If set, holds back endpoint removals crossing this threshold.
+    pub panic_threshold: Option<PanicThreshold>,
 }
 
 impl DiscoveryConfig {
@@ -104,7 +311,21 @@ impl DiscoveryConfig {
         Self {
             service_name: service_name.into(),
             namespace: None,
+            client: None,
             port: port.into(),
+            retry: RetryPolicy::default(),
+            min_endpoints: 1,
+            readiness: ReadinessPolicy::default(),
+            additional_services: Vec::new(),
+            label_selector: None,
+            field_selector: None,
+            all_namespaces: None,
+            zone_aware: None,
+            node_local: None,
+            weights: None,
+            subset: None,
+            settle_window: None,
+            panic_threshold: None,
         }
     }
 
@@ -114,6 +335,204 @@ impl DiscoveryConfig {
         self.namespace = Some(namespace.into());
         self
     }
+
+    /// Watches the service in all namespaces passing the filter, using a single
+    /// cluster-wide watch.
+    ///
+    /// The endpoints of all namespaces are sent to the same channel, so a balance
+    /// channel fed directly would spread requests across namespaces, e.g. across
+    /// tenants. To route per namespace, use [`EndpointKey`](crate::EndpointKey)
+    /// keys, which carry the namespace like [`DiscoveredEndpoint::namespace`], and
+    /// forward each change to a channel per namespace.
+    ///
+    /// Requires a `ClusterRole` allowing to list and watch `EndpointSlice` resources.
+    /// The namespace, if set, is then only used to look up a [`Port::ServicePort`].
+    #[must_use]
+    pub fn all_namespaces(mut self, filter: NamespaceFilter) -> Self {
+        self.all_namespaces = Some(filter);
+        self
+    }
+
+    /// Sets the Kubernetes client used to watch endpoints.
+    ///
+    /// Useful to share a client with the rest of the application, or to use a
+    /// specific kubeconfig context, impersonation or custom timeouts. Unless an
+    /// explicit namespace is set, the client's default namespace is used.
+    #[must_use]
+    pub fn client(mut self, client: Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Sets the minimum number of endpoints required before discovery is considered ready.
+    ///
+    /// With 0, discovery is ready once the initial list of `EndpointSlice` resources
+    /// completes, even if no endpoints were found.
+    #[must_use]
+    pub fn min_endpoints(mut self, min_endpoints: usize) -> Self {
+        self.min_endpoints = min_endpoints;
+        self
+    }
+
+    /// Sets the retry policy for client construction and watch failures.
+    #[must_use]
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Sets which endpoints are sent to the channel, based on their conditions.
+    #[must_use]
+    pub fn readiness(mut self, readiness: ReadinessPolicy) -> Self {
+        self.readiness = readiness;
+        self
+    }
+
+    /// Adds a Service whose endpoints are merged into the same channel.
+    ///
+    /// Useful when the same gRPC API is served behind several Services, e.g.
+    /// regional shards or old and new deployments. An endpoint contributed by
+    /// several Services is only removed once none of them contains it.
+    #[must_use]
+    pub fn add_service(mut self, service: ServiceRef) -> Self {
+        self.additional_services.push(service);
+        self
+    }
+
+    /// Sets a label selector replacing the default `kubernetes.io/service-name` selector.
+    ///
+    /// Useful to discover slices by custom labels, e.g. `app.kubernetes.io/part-of=payments`,
+    /// or slices managed by custom controllers. The service name is then only used
+    /// for logging and to look up a [`Port::ServicePort`].
+    #[must_use]
+    pub fn label_selector(mut self, selector: impl Into<String>) -> Self {
+        self.label_selector = Some(selector.into());
+        self
+    }
+
+    /// Sets a field selector for the `EndpointSlice` resources to watch.
+    #[must_use]
+    pub fn field_selector(mut self, selector: impl Into<String>) -> Self {
+        self.field_selector = Some(selector.into());
+        self
+    }
+
+    /// Prefers endpoints in the client's own zone, falling back to all zones when
+    /// the zone has too few endpoints.
+    ///
+    /// Reading the zone from the client's `Node` requires permission to get `Node`
+    /// resources. The zone is read in the background, using endpoints of all zones
+    /// until it is known, or if it cannot be determined.
+    #[must_use]
+    pub fn zone_aware(mut self, zone_aware: ZoneAware) -> Self {
+        self.zone_aware = Some(zone_aware);
+        self
+    }
+
+    /// Prefers or requires endpoints on the client's own node, e.g. to reach the pod
+    /// of a `DaemonSet` on the same node.
+    ///
+    /// Takes precedence over [`DiscoveryConfig::zone_aware`]: the zone's endpoints
+    /// are only used when the node has none and traffic may leave it. Discovery
+    /// fails with [`Error::UnknownNode`] if traffic must stay on the node but its
+    /// name is unknown.
+    #[must_use]
+    pub fn node_local(mut self, node_local: NodeLocal) -> Self {
+        self.node_local = Some(node_local);
+        self
+    }
+
+    /// Reads the weight of each endpoint from an annotation or label of its pod.
+    ///
+    /// The weight is available to the build function as
+    /// [`DiscoveredEndpoint::weight`] and is carried by [`Weighted`](crate::Weighted)
+    /// keys, e.g. for a [`WeightedChannel`](crate::WeightedChannel). The pods that
+    /// may carry a weight are watched once per namespace, or once cluster-wide if
+    /// any Service is watched in all namespaces, which requires permission to list
+    /// and watch `Pod` resources.
+    #[must_use]
+    pub fn weights(mut self, source: WeightSource) -> Self {
+        self.weights = Some(source);
+        self
+    }
+
+    /// Sends only a bounded, stable subset of the endpoints to the channel,
+    /// chosen by the identity of the client.
+    ///
+    /// Limits the number of connections when many clients talk to many backends.
+    /// Subsets are chosen among the endpoints preferred by the node-local and
+    /// zone-aware settings. If the client's identity is unknown, a random one is
+    /// used, so the subset changes when the client restarts.
+    #[must_use]
+    pub fn subset(mut self, subset: Subset) -> Self {
+        self.subset = Some(subset);
+        self
+    }
+
+    /// Collects endpoint changes for the given duration after the first change,
+    /// then sends their net effect at once.
+    ///
+    /// Reduces churn in the balance channel during rolling deployments: an
+    /// endpoint inserted and removed within the window is never sent, and an
+    /// endpoint removed and inserted again keeps its connection.
+    #[must_use]
+    pub fn settle_window(mut self, window: Duration) -> Self {
+        self.settle_window = Some(window);
+        self
+    }
+
+    /// Holds back endpoint removals that would cross the given threshold.
+    ///
+    /// Protects clients against an empty or truncated view of the endpoints, e.g.
+    /// after a bad API response or a selector change: held back removals are
+    /// logged, and proceed once the threshold allows it or they persist for the
+    /// confirmation delay. An endpoint coming back in the meantime keeps its
+    /// connection.
+    #[must_use]
+    pub fn panic_threshold(mut self, threshold: PanicThreshold) -> Self {
+        self.panic_threshold = Some(threshold);
+        self
+    }
+
+    /// Returns all Services to watch, starting with the primary one.
+    fn services(&self) -> Vec<ServiceRef> {
+        let primary = ServiceRef {
+            name: self.service_name.clone(),
+            namespace: self.namespace.clone(),
+            port: self.port.clone(),
+            label_selector: self.label_selector.clone(),
+            field_selector: self.field_selector.clone(),
+            all_namespaces: self.all_namespaces.clone(),
+        };
+
+        std::iter::once(primary)
+            .chain(self.additional_services.iter().cloned())
+            .collect()
+    }
+}
+
+impl fmt::Debug for DiscoveryConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DiscoveryConfig")
+            .field("service_name", &self.service_name)
+            .field("namespace", &self.namespace)
+            .field("client", &self.client.as_ref().map(|_| "Client"))
+            .field("port", &self.port)
+            .field("retry", &self.retry)
+            .field("min_endpoints", &self.min_endpoints)
+            .field("readiness", &self.readiness)
+            .field("additional_services", &self.additional_services)
+            .field("label_selector", &self.label_selector)
+            .field("field_selector", &self.field_selector)
+            .field("all_namespaces", &self.all_namespaces)
+            .field("zone_aware", &self.zone_aware)
+            .field("node_local", &self.node_local)
+            .field("weights", &self.weights)
+            .field("subset", &self.subset)
+            .field("settle_window", &self.settle_window)
+            .field("panic_threshold", &self.panic_threshold)
+            .finish()
+    }
 }
 
 /// Starts watching Kubernetes endpoints and sends changes to the provided sender.
@@ -122,16 +541,30 @@ impl DiscoveryConfig {
 /// for the specified service and sends `Change` events to the provided sender.
 /// The user is responsible for creating the balance channel and building endpoints.
 ///
+/// Failures are retried according to the configured [`RetryPolicy`], keeping the
+/// last known endpoints in the channel. The task only stops when the channel is
+/// closed, a failure is deemed fatal by the policy, or it is shut down through the
+/// returned [`DiscoveryHandle`]. The terminal [`Error`] is available through
+/// [`DiscoveryHandle::join`].
+///
 /// # Arguments
 ///
 /// * `config` - Discovery configuration specifying the service to watch
 /// * `tx` - Sender for endpoint changes (from `Channel::balance_channel()`)
-/// * `build` - Function to build a key and `Endpoint` from a `SocketAddr`
+/// * `build` - Function to build an `Endpoint` from a [`DiscoveredEndpoint`]
+///
+/// The channel key can be any [`DiscoveryKey`]. With `SocketAddr` keys, a pod
+/// replaced by another pod reusing its IP address keeps the existing connection;
+/// use [`EndpointKey`](crate::EndpointKey) to rebuild the connection instead.
+///
+/// See [`try_discover`] and [`discover_async`] for fallible and asynchronous
+/// build functions.
 ///
 /// # Requirements
 ///
 /// - The application must have RBAC permissions to watch `EndpointSlice` resources
-/// - Kubernetes client configuration (in-cluster or kubeconfig)
+/// - Kubernetes client configuration (in-cluster or kubeconfig), unless a client
+///   is provided through [`DiscoveryConfig::client`]
 ///
 /// # Example
 ///
@@ -144,552 +577,3365 @@ impl DiscoveryConfig {
 /// let (channel, tx) = Channel::balance_channel::<SocketAddr>(1024);
 ///
 /// let config = DiscoveryConfig::new("my-grpc-service", 50051);
-/// discover(config, tx, |addr| {
-///     Endpoint::from_shared(format!("http://{addr}"))
+/// let handle = discover(config, tx, |endpoint| {
+///     Endpoint::from_shared(format!("http://{}", endpoint.address))
 ///         .unwrap()
 ///         .connect_timeout(Duration::from_secs(5))
 /// });
 ///
+/// // Wait for the initial endpoints before issuing the first request
+/// handle.ready_timeout(Duration::from_secs(10)).await?;
+///
 /// // Use with your generated gRPC client
 /// let client = MyServiceClient::new(channel);
+///
+/// // Stop discovery on application shutdown
+/// handle.shutdown();
+/// handle.join().await?;
 /// ```
-pub fn discover<F>(config: DiscoveryConfig, tx: Sender<Change<SocketAddr, Endpoint>>, build: F)
+pub fn discover<K, F>(
+    config: DiscoveryConfig,
+    tx: Sender<Change<K, Endpoint>>,
+    build: F,
+) -> DiscoveryHandle
 where
-    F: Fn(SocketAddr) -> Endpoint + Send + 'static,
+    K: DiscoveryKey,
+    F: Fn(&DiscoveredEndpoint) -> Endpoint + Send + 'static,
 {
-    tokio::spawn(async move {
-        if let Err(e) = discovery_loop(tx, config, build).await {
-            error!("Kubernetes endpoint watcher failed: {e}");
-        }
-    });
+    discover_async(config, tx, move |endpoint| {
+        future::ready(Ok::<_, Infallible>(build(&endpoint)))
+    })
 }
 
-/// Background task that watches `EndpointSlice` resources and sends endpoint changes.
-async fn discovery_loop<F>(
-    tx: Sender<Change<SocketAddr, Endpoint>>,
+/// Starts watching Kubernetes endpoints using a fallible build function.
+///
+/// Works like [`discover`], except that building an `Endpoint` may fail. Failures
+/// are logged, counted (see [`DiscoveryHandle::build_failures`]) and retried on the
+/// next endpoint update instead of stopping discovery.
+///
+/// # Example
+///
+/// ```ignore
+/// use std::net::SocketAddr;
+/// use std::time::Duration;
+/// use tonic::transport::{Channel, ClientTlsConfig, Endpoint};
+/// use tonic_lb_k8s::{try_discover, DiscoveryConfig};
+///
+/// let (channel, tx) = Channel::balance_channel::<SocketAddr>(1024);
+///
+/// let config = DiscoveryConfig::new("my-grpc-service", 50051);
+/// let tls = ClientTlsConfig::new();
+///
+/// let handle = try_discover(config, tx, move |endpoint| {
+///     Endpoint::from_shared(format!("https://{}", endpoint.address))
+///         .and_then(|endpoint| endpoint.tls_config(tls.clone()))
+///         .map(|endpoint| endpoint.connect_timeout(Duration::from_secs(5)))
+/// });
+/// ```
+pub fn try_discover<K, F, E>(
     config: DiscoveryConfig,
+    tx: Sender<Change<K, Endpoint>>,
     build: F,
-) -> Result<()>
+) -> DiscoveryHandle
 where
-    F: Fn(SocketAddr) -> Endpoint,
+    K: DiscoveryKey,
+    F: Fn(&DiscoveredEndpoint) -> std::result::Result<Endpoint, E> + Send + 'static,
+    E: Into<BoxError> + Send + 'static,
 {
-    let client = Client::try_default().await?;
-    let namespace = config
-        .namespace
-        .unwrap_or_else(|| client.default_namespace().to_string());
-    let slices: Api<EndpointSlice> = Api::namespaced(client, &namespace);
-
-    let label_selector = format!("kubernetes.io/service-name={}", config.service_name);
-    let watcher_config = WatcherConfig::default().labels(&label_selector);
-
-    let mut known: HashSet<SocketAddr> = HashSet::new();
-    let stream = watcher::watcher(slices, watcher_config).default_backoff();
-    tokio::pin!(stream);
+    discover_async(config, tx, move |endpoint| future::ready(build(&endpoint)))
+}
 
-    debug!(
-        "Starting Kubernetes endpoint watch for {namespace}/{} on port {:?}",
-        config.service_name, config.port
-    );
+/// Starts watching Kubernetes endpoints using an asynchronous, fallible build function.
+///
+/// Works like [`try_discover`], except that the build function returns a future,
+/// e.g. to fetch credentials for each endpoint. The discovered endpoint is passed
+/// by value so that the returned future can own it.
+///
+/// # Example
+///
+/// ```ignore
+/// use std::net::SocketAddr;
+/// use tonic::transport::{Channel, Endpoint};
+/// use tonic_lb_k8s::{discover_async, DiscoveryConfig};
+///
+/// let (channel, tx) = Channel::balance_channel::<SocketAddr>(1024);
+///
+/// let config = DiscoveryConfig::new("my-grpc-service", 50051);
+/// let handle = discover_async(config, tx, |endpoint| async move {
+///     let tls = fetch_tls_config(endpoint.pod_name()).await?;
+///     Endpoint::from_shared(format!("https://{}", endpoint.address))?.tls_config(tls)
+/// });
+/// ```
+pub fn discover_async<K, F, Fut, E>(
+    config: DiscoveryConfig,
+    tx: Sender<Change<K, Endpoint>>,
+    build: F,
+) -> DiscoveryHandle
+where
+    K: DiscoveryKey,
+    F: Fn(DiscoveredEndpoint) -> Fut + Send + 'static,
+    Fut: Future<Output = std::result::Result<Endpoint, E>> + Send + 'static,
+    E: Into<BoxError> + Send + 'static,
+{
+    handle::spawn(config.min_endpoints, |state| {
+        discovery_loop(config, Publisher::new(tx, build), state)
+    })
+}
 
-    while let Some(event) = stream.try_next().await? {
-        let actions = process_event(&event, &mut known, &config.port);
+/// Background task that watches `EndpointSlice` resources and sends endpoint changes.
+async fn discovery_loop<K, F, Fut, E>(
+    config: DiscoveryConfig,
+    mut publisher: Publisher<K, F>,
+    state: watch::Sender<State>,
+) -> Result<()>
+where
+    K: DiscoveryKey,
+    F: Fn(DiscoveredEndpoint) -> Fut,
+    Fut: Future<Output = std::result::Result<Endpoint, E>>,
+    E: Into<BoxError>,
+{
+    // The node name does not change while running, so an unknown one is not retried
+    let mut selection = selection(&config)?;
+
+    let (client, mut sources) = connect(&config, &state).await?;
+    let (owners, mut stream) = watch_sources(&mut sources, &client, &config);
+    let mut failing = vec![false; owners.len()];
+
+    // The zone is looked up in the background, so that a failing lookup does not
+    // hold up discovery
+    let zone = zone_preference(&config, &client);
+    tokio::pin!(zone);
+    let mut zone_pending = true;
+
+    let mut batch = Batch::new(config.settle_window);
+    let mut guard = PanicGuard::new(config.panic_threshold.clone());
+
+    loop {
+        // Collected changes are flushed once the settle window elapses, and held
+        // back removals are reconsidered once they may proceed
+        let deadline = batch.deadline().into_iter().chain(guard.deadline()).min();
+        let wake = tokio::select! {
+            next = stream.next() => Wake::Watch(next),
+            zone = &mut zone, if zone_pending => Wake::Zone(zone),
+            () = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)),
+                if deadline.is_some() => Wake::Deadline,
+        };
 
-        for action in actions {
-            let change = match action {
-                EndpointAction::Insert(addr) => Change::Insert(addr, build(addr)),
-                EndpointAction::Remove(addr) => Change::Remove(addr),
-            };
+        let changes = match wake {
+            Wake::Watch(Some((id, Watched::Event(event)))) => {
+                failing[id] = false;
+                let mut changes = Vec::new();
+                for &index in &owners[id] {
+                    let candidates = sources[index].handle(&event, config.readiness);
+                    changes.extend(batch.add(selection.update(index, candidates)));
+
+                    debug!(
+                        "Kubernetes discovery: {} of {} endpoints selected after update of {}/{}",
+                        selection.len(),
+                        sources.iter().map(|s| s.tracker.len()).sum::<usize>(),
+                        sources[index].namespace,
+                        sources[index].name
+                    );
+                }
 
-            if tx.send(change).await.is_err() {
-                warn!("channel closed, stopping Kubernetes watcher");
-                return Ok(());
+                changes
             }
-        }
 
-        debug!(
-            "Kubernetes discovery: {} endpoints for {namespace}/{}",
-            known.len(),
-            config.service_name
-        );
-    }
+            Wake::Watch(Some((id, Watched::Failed(e, delay)))) => {
+                let Some(delay) = delay else {
+                    return Err(e);
+                };
 
-    Ok(())
-}
+                warn!(
+                    "Kubernetes watch failed ({:?}), retrying in {delay:?}: {e}",
+                    e.kind()
+                );
 
-/// Represents an endpoint change action.
-#[derive(Debug, Clone, PartialEq, Eq)]
-enum EndpointAction {
-    Insert(SocketAddr),
-    Remove(SocketAddr),
-}
+                failing[id] = true;
+                handle::update(&state, |s| s.status = DiscoveryStatus::Degraded);
+                continue;
+            }
 
-/// Processes a watcher event and returns the endpoint actions.
-///
-/// This function is extracted to enable unit testing of the event processing logic.
-fn process_event(
-    event: &Event<EndpointSlice>,
-    known: &mut HashSet<SocketAddr>,
-    port: &Port,
-) -> Vec<EndpointAction> {
-    match event {
-        Event::Apply(slice) | Event::InitApply(slice) => {
-            let current = extract_ready_endpoints(slice, port);
-            let mut actions = Vec::new();
-
-            for addr in current {
-                if known.insert(addr) {
-                    debug!("adding endpoint: {addr}");
-                    actions.push(EndpointAction::Insert(addr));
-                }
+            Wake::Watch(None) => return Ok(()),
+            Wake::Zone(zone) => {
+                zone_pending = false;
+                zone.map(|zone| batch.add(selection.prefer_zone(zone)))
+                    .unwrap_or_default()
             }
 
-            actions
-        }
+            Wake::Deadline if batch.deadline().is_some_and(|d| d <= Instant::now()) => {
+                batch.flush()
+            }
+
+            Wake::Deadline => Vec::new(),
+        };
 
-        Event::Delete(slice) => {
-            let removed = extract_ready_endpoints(slice, port);
-            let mut actions = Vec::new();
+        publisher
+            .publish(guard.apply(changes, Instant::now()))
+            .await?;
 
-            for addr in removed {
-                if known.remove(&addr) {
-                    debug!("removing endpoint: {addr}");
-                    actions.push(EndpointAction::Remove(addr));
-                }
+        let synced = sources.iter().all(|s| s.synced);
+        let degraded = failing.contains(&true);
+        handle::update(&state, |s| {
+            if synced {
+                s.initialized = true;
             }
 
-            actions
-        }
+            // Degraded until every failing watch recovered
+            if degraded {
+                s.status = DiscoveryStatus::Degraded;
+            } else if synced {
+                s.status = DiscoveryStatus::Synced;
+            }
 
-        Event::Init | Event::InitDone => {
-            debug!("Kubernetes watcher initialization event");
-            Vec::new()
-        }
+            s.slices = sources.iter().map(|s| s.tracker.slice_count()).sum();
+            s.slices_with_port = sources.iter().map(|s| s.tracker.slices_with_port()).sum();
+            s.endpoints = publisher.published();
+            s.build_failures = publisher.build_failures();
+        });
     }
 }
 
-/// Extracts ready endpoint addresses from an `EndpointSlice`.
-fn extract_ready_endpoints(slice: &EndpointSlice, port: &Port) -> HashSet<SocketAddr> {
-    // Resolve the port number
-    let port_number = match port {
-        Port::Number(n) => Some(*n),
-        Port::Name(name) => slice.ports.as_ref().and_then(|ports| {
-            ports
-                .iter()
-                .find(|p| p.name.as_deref() == Some(name.as_str()))
-                .and_then(|p| p.port)
-                .and_then(|p| u16::try_from(p).ok())
-        }),
-    };
-
-    let Some(port_number) = port_number else {
-        return HashSet::new();
+/// Creates the client and prepares the watched Services, retrying failures.
+async fn connect<K: DiscoveryKey>(
+    config: &DiscoveryConfig,
+    state: &watch::Sender<State>,
+) -> Result<(Client, Vec<Source<K>>)> {
+    let mut failures = 0;
+    let client = match config.client.clone() {
+        Some(client) => client,
+        None => loop {
+            match Client::try_default().await {
+                Ok(client) => break client,
+                Err(e) => backoff(&config.retry, &mut failures, state, Error::Config(e)).await?,
+            }
+        },
     };
 
-    let mut addrs = HashSet::new();
+    let mut sources = Vec::new();
+    for service in config.services() {
+        let source = loop {
+            let namespace = config.namespace.as_deref();
+            match Source::new(&client, namespace, service.clone()).await {
+                Ok(source) => break source,
+                Err(e) => backoff(&config.retry, &mut failures, state, e).await?,
+            }
+        };
 
-    for ep in &slice.endpoints {
-        // An endpoint is ready if conditions.ready is true or unset (defaults to true)
-        let ready = ep.conditions.as_ref().and_then(|c| c.ready).unwrap_or(true);
+        sources.push(source);
+    }
 
-        if !ready {
-            continue;
-        }
+    Ok((client, sources))
+}
 
-        for addr in &ep.addresses {
-            if let Ok(ip) = addr.parse::<IpAddr>() {
-                addrs.insert(SocketAddr::new(ip, port_number));
-            }
-        }
+/// Starts the watches of all Services, each retried on its own.
+///
+/// Events are tagged with the index of their watch, and the returned owners map
+/// each watch to the indices of the Services it feeds.
+fn watch_sources<K: DiscoveryKey>(
+    sources: &mut [Source<K>],
+    client: &Client,
+    config: &DiscoveryConfig,
+) -> (Vec<Vec<usize>>, Watches) {
+    let mut watches: Vec<_> = sources
+        .iter()
+        .enumerate()
+        .map(|(index, source)| (vec![index], source.watch(client)))
+        .collect();
+
+    if let Some(weights) = &config.weights {
+        watches.extend(watch_pods(sources, client, weights));
     }
 
-    addrs
+    let owners = watches.iter().map(|(owners, _)| owners.clone()).collect();
+    let stream = stream::select_all(watches.into_iter().enumerate().map(|(id, (_, watch))| {
+        retrying(watch, config.retry.clone())
+            .map(move |item| (id, item))
+            .boxed()
+    }));
+
+    (owners, stream)
 }
 
-#[cfg(test)]
+/// Starts the watches of the pods carrying weights, with the Services they feed.
+///
+/// The Services of a namespace share a watch and its cache of weights. If any
+/// Service is watched cluster-wide, the pods of all namespaces are watched once
+/// for all Services.
+fn watch_pods<K: DiscoveryKey>(
+    sources: &mut [Source<K>],
+    client: &Client,
+    source: &WeightSource,
+) -> Vec<(Vec<usize>, BoxStream<'static, watcher::Result<WatchEvent>>)> {
+    let cluster_wide = sources.iter().any(|s| s.filter.is_some());
+    let mut scopes: Vec<(Option<String>, Vec<usize>)> = Vec::new();
+    for (index, s) in sources.iter().enumerate() {
+        let namespace = (!cluster_wide).then(|| s.namespace.clone());
+        match scopes.iter_mut().find(|(scope, _)| *scope == namespace) {
+            Some((_, owners)) => owners.push(index),
+            None => scopes.push((namespace, vec![index])),
+        }
+    }
+
+    let mut watches = Vec::new();
+    for (namespace, owners) in scopes {
+        let weights = SharedWeights::new(Mutex::new(Weights::new(Some(source.clone()))));
+        for &index in &owners {
+            sources[index].tracker = EndpointTracker::weighted(weights.clone());
+        }
+
+        let pods: Api<Pod> = match &namespace {
+            Some(namespace) => Api::namespaced(client.clone(), namespace),
+            None => Api::all(client.clone()),
+        };
+
+        debug!(
+            "Starting Kubernetes pod watch for {} to weigh endpoints",
+            namespace.as_deref().unwrap_or("*")
+        );
+
+        let watch = watcher::metadata_watcher(pods, source.watcher_config())
+            .map_ok(move |event| {
+                WatchEvent::Pods(weights.lock().expect("weights lock").apply(&event))
+            })
+            .boxed();
+
+        watches.push((owners, watch));
+    }
+
+    watches
+}
+
+/// Creates the selection, resolving the client's node and identity as configured.
+fn selection<K: DiscoveryKey>(config: &DiscoveryConfig) -> Result<Selection<K>> {
+    let mut selection = Selection::new(config.readiness);
+    if let Some(node) = node_preference(config)? {
+        selection = selection.prefer_node(node);
+    }
+
+    if let Some(subset) = config.subset.as_ref().map(subsetter) {
+        selection = selection.subset(subset);
+    }
+
+    Ok(selection)
+}
+
+/// A watched `Service` and the endpoints it contributes.
+///
+/// Each Service has its own tracker, so that re-listing the slices of one Service
+/// never affects the endpoints contributed by another.
+struct Source<K> {
+    /// The Service name.
+    name: String,
+
+    /// The namespace of the Service.
+    namespace: String,
+
+    /// The port, with Service ports mapped to their name.
+    port: Port,
+
+    /// Selects the Service's `EndpointSlice` resources.
+    watcher_config: WatcherConfig,
+
+    /// Namespaces watched, if the Service is watched cluster-wide.
+    filter: Option<NamespaceFilter>,
+
+    /// The state of the Service's `EndpointSlice` resources. Outlives watch failures
+    /// so that known endpoints stay in the channel until the watcher re-lists.
+    tracker: EndpointTracker<K>,
+
+    /// Whether the initial list of the Service's `EndpointSlice` resources completed.
+    synced: bool,
+}
+
+impl<K: DiscoveryKey> Source<K> {
+    /// Prepares a Service for watching, mapping a [`Port::ServicePort`] to its name.
+    ///
+    /// Unless set for the Service, the namespace of the configuration or else the
+    /// client's default namespace is used.
+    async fn new(client: &Client, namespace: Option<&str>, service: ServiceRef) -> Result<Self> {
+        let namespace = service
+            .namespace
+            .as_deref()
+            .or(namespace)
+            .unwrap_or_else(|| client.default_namespace())
+            .to_string();
+
+        let port = map_service_port(client, &namespace, &service.name, &service.port).await?;
+        let scope = match service.all_namespaces {
+            Some(_) => "*",
+            None => &namespace,
+        };
+
+        debug!(
+            "Starting Kubernetes endpoint watch for {scope}/{} on port {port:?}",
+            service.name
+        );
+
+        Ok(Self {
+            watcher_config: service.watcher_config(),
+            filter: service.all_namespaces,
+            name: service.name,
+            namespace,
+            port,
+            tracker: EndpointTracker::default(),
+            synced: false,
+        })
+    }
+
+    /// Starts the watch of the Service's `EndpointSlice` resources.
+    fn watch(&self, client: &Client) -> BoxStream<'static, watcher::Result<WatchEvent>> {
+        let slices: Api<EndpointSlice> = match self.filter {
+            Some(_) => Api::all(client.clone()),
+            None => Api::namespaced(client.clone(), &self.namespace),
+        };
+
+        watcher::watcher(slices, self.watcher_config.clone())
+            .map_ok(|event| WatchEvent::Slices(Box::new(event)))
+            .boxed()
+    }
+
+    /// Handles an event of one of the watches and returns the candidate changes.
+    fn handle(&mut self, event: &WatchEvent, readiness: ReadinessPolicy) -> Vec<EndpointAction<K>> {
+        let event = match event {
+            WatchEvent::Pods(true) => return self.tracker.reweigh(),
+            WatchEvent::Pods(false) => return Vec::new(),
+            WatchEvent::Slices(event) => &**event,
+        };
+
+        if self
+            .filter
+            .as_ref()
+            .is_some_and(|filter| !filter.admits(event))
+        {
+            return Vec::new();
+        }
+
+        match event {
+            Event::Init => self.synced = false,
+            Event::InitDone => self.synced = true,
+            _ => {}
+        }
+
+        process_event(event, &mut self.tracker, &self.port, readiness)
+    }
+}
+
+/// An event of one of the watches of a Service.
+enum WatchEvent {
+    /// An event of the Service's `EndpointSlice` watch.
+    Slices(Box<Event<EndpointSlice>>),
+
+    /// An event of the shared watch of the pods carrying weights, already applied
+    /// to their cache, and whether it changed any weight.
+    Pods(bool),
+}
+
+/// An item of a watch retried on its own.
+enum Watched<T> {
+    /// An event of the watch.
+    Event(T),
+
+    /// The watch failed. It is retried after the delay, or not at all if the retry
+    /// policy gave up.
+    Failed(Error, Option<Duration>),
+}
+
+/// Retries a watch according to the policy.
+///
+/// Each watch counts its own consecutive failures and backs off without holding
+/// up the events of the other watches.
+fn retrying<T: Send + 'static>(
+    watch: BoxStream<'static, watcher::Result<T>>,
+    policy: RetryPolicy,
+) -> BoxStream<'static, Watched<T>> {
+    let state = (watch, 0_u32, None::<Duration>);
+    stream::unfold(state, move |(mut watch, failures, delay)| {
+        let policy = policy.clone();
+        async move {
+            if let Some(delay) = delay {
+                tokio::time::sleep(delay).await;
+            }
+
+            match watch.next().await? {
+                Ok(event) => Some((Watched::Event(event), (watch, 0, None))),
+                Err(e) => {
+                    let failures = failures.saturating_add(1);
+                    let err = Error::from(e);
+                    let delay = policy.next_delay(err.kind(), failures);
+                    Some((Watched::Failed(err, delay), (watch, failures, delay)))
+                }
+            }
+        }
+    })
+    .boxed()
+}
+
+/// The merged watches of all Services, with events tagged with their watch.
+type Watches = stream::SelectAll<BoxStream<'static, (usize, Watched<WatchEvent>)>>;
+
+/// What woke up the discovery loop.
+enum Wake<T> {
+    /// An item of the watches, or `None` once they ended.
+    Watch(Option<T>),
+
+    /// The client's zone was resolved, if it could be.
+    Zone(Option<ZonePreference>),
+
+    /// The settle window or a held back removal is due.
+    Deadline,
+}
+
+/// Resolves the client's zone if discovery is zone-aware.
+///
+/// Failed lookups of the client's `Node` are retried per the retry policy, except
+/// when the `Node` cannot be read at all, e.g. without permission to get it. If
+/// the zone cannot be determined, endpoints of all zones are used.
+async fn zone_preference(config: &DiscoveryConfig, client: &Client) -> Option<ZonePreference> {
+    let zone_aware = config.zone_aware.as_ref()?;
+
+    let mut failures = 0;
+    let zone = loop {
+        let e = match zone_aware.zone.resolve(client).await {
+            Ok(zone) => break zone,
+            Err(e) => e,
+        };
+
+        failures += 1;
+        let kind = e.kind();
+        let delay = match kind {
+            ErrorKind::Forbidden | ErrorKind::NotFound => None,
+            _ => config.retry.next_delay(kind, failures),
+        };
+
+        let Some(delay) = delay else {
+            warn!(
+                "zone of the client cannot be read ({kind:?}), using endpoints of all zones: {e}"
+            );
+            return None;
+        };
+
+        warn!("reading the zone of the client failed ({kind:?}), retrying in {delay:?}: {e}");
+        tokio::time::sleep(delay).await;
+    };
+
+    let Some(zone) = zone else {
+        warn!(
+            "zone of the client is unknown ({:?}), using endpoints of all zones",
+            zone_aware.zone
+        );
+        return None;
+    };
+
+    debug!("Kubernetes discovery: preferring endpoints in zone {zone}");
+    Some(ZonePreference {
+        zone,
+        min_endpoints: zone_aware.min_endpoints,
+    })
+}
+
+/// Resolves the client's node if discovery is node-local.
+fn node_preference(config: &DiscoveryConfig) -> Result<Option<NodePreference>> {
+    let Some(node_local) = &config.node_local else {
+        return Ok(None);
+    };
+
+    let Some(node) = node_local.resolve()? else {
+        warn!(
+            "node of the client is unknown ({:?}), using endpoints of all nodes",
+            node_local.node
+        );
+        return Ok(None);
+    };
+
+    debug!(
+        "Kubernetes discovery: preferring endpoints on node {} ({:?})",
+        node.node, node.mode
+    );
+    Ok(Some(node))
+}
+
+/// Resolves the client's identity for subsetting.
+fn subsetter(subset: &Subset) -> Subsetter {
+    let client = subset.client_id.resolve().unwrap_or_else(|| {
+        let client = format!("{:016x}", fastrand::u64(..));
+        warn!(
+            "identity of the client is unknown ({:?}), using random identity {client}",
+            subset.client_id
+        );
+        client
+    });
+
+    debug!(
+        "Kubernetes discovery: selecting a subset of {} endpoints for {client}",
+        subset.size
+    );
+
+    Subsetter {
+        client,
+        size: subset.size,
+    }
+}
+
+/// Maps a [`Port::ServicePort`] to the name of the matching `Service` port.
+///
+/// `EndpointSlice` ports carry the name of the `Service` port they were derived
+/// from, with the number of its target port. Other ports are returned as is.
+async fn map_service_port(
+    client: &Client,
+    namespace: &str,
+    service_name: &str,
+    port: &Port,
+) -> Result<Port> {
+    let Port::ServicePort(number) = port else {
+        return Ok(port.clone());
+    };
+
+    let services: Api<Service> = Api::namespaced(client.clone(), namespace);
+    let service = services.get(service_name).await?;
+
+    service_port_name(&service, *number)
+        .map(Port::Name)
+        .ok_or(Error::ServicePortNotFound { port: *number })
+}
+
+/// Returns the name of the `Service` port with the given number, if it exists.
+fn service_port_name(service: &Service, number: u16) -> Option<String> {
+    service
+        .spec
+        .as_ref()?
+        .ports
+        .as_ref()?
+        .iter()
+        .find(|p| p.port == i32::from(number))
+        .map(|p| p.name.clone().unwrap_or_default())
+}
+
+/// Waits before retrying a failed operation, or returns the error if the policy gives up.
+async fn backoff(
+    policy: &RetryPolicy,
+    failures: &mut u32,
+    state: &watch::Sender<State>,
+    err: Error,
+) -> Result<()> {
+    *failures = failures.saturating_add(1);
+
+    let kind = err.kind();
+    let Some(delay) = policy.next_delay(kind, *failures) else {
+        return Err(err);
+    };
+
+    handle::update(state, |s| s.status = DiscoveryStatus::Degraded);
+    warn!("Kubernetes discovery failed ({kind:?}), retrying in {delay:?}: {err}");
+    tokio::time::sleep(delay).await;
+    Ok(())
+}
+
+/// Represents an endpoint change action.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum EndpointAction<K> {
+    Insert(K, Box<DiscoveredEndpoint>),
+    /// The metadata of a known endpoint changed, e.g. it started terminating.
+    Update(K, Box<DiscoveredEndpoint>),
+    Remove(K),
+}
+
+/// Tracks the ready endpoints contributed by each `EndpointSlice`.
+///
+/// Remembering the last seen endpoints per slice allows updates to be diffed
+/// against the previous state, so that endpoints dropped from a slice (e.g. a pod
+/// becoming not ready or being scaled down) are removed from the channel.
+///
+/// The same endpoint may transiently appear in more than one slice while the
+/// `EndpointSlice` controller rebalances endpoints, so each key is reference
+/// counted and only removed once the last slice containing it drops it.
+///
+/// When the watcher re-lists (e.g. after a desync), the listed slices are buffered
+/// until the list completes and then reconciled against the known state in one go.
+#[derive(Debug)]
+struct EndpointTracker<K> {
+    /// State last seen in each slice, keyed by slice identity.
+    slices: HashMap<String, SliceState<K>>,
+
+    /// Number of slices containing each key currently sent to the channel.
+    refs: HashMap<K, usize>,
+
+    /// Slices received since the last `Init` event, if a re-list is in progress.
+    pending: Option<HashMap<String, SliceState<K>>>,
+
+    /// Weights of the pods backing the endpoints, shared with the Services whose
+    /// pods are watched together.
+    weights: SharedWeights,
+}
+
+impl<K> Default for EndpointTracker<K> {
+    fn default() -> Self {
+        Self {
+            slices: HashMap::new(),
+            refs: HashMap::new(),
+            pending: None,
+            weights: SharedWeights::default(),
+        }
+    }
+}
+
+/// The resolved port and ready endpoints of a single `EndpointSlice`.
+#[derive(Debug, Clone)]
+struct SliceState<K> {
+    /// The resolved port number, if the slice exposes the configured port.
+    port: Option<u16>,
+
+    /// The ready endpoints, keyed by channel key.
+    endpoints: HashMap<K, DiscoveredEndpoint>,
+}
+
+impl<K> Default for SliceState<K> {
+    fn default() -> Self {
+        Self {
+            port: None,
+            endpoints: HashMap::new(),
+        }
+    }
+}
+
+impl<K: DiscoveryKey> SliceState<K> {
+    /// Extracts the state of a slice for the configured port and readiness policy.
+    fn new(slice: &EndpointSlice, port: &Port, readiness: ReadinessPolicy) -> Self {
+        Self {
+            port: resolve_port(slice, port).map(|(number, _)| number),
+            endpoints: extract_endpoints(slice, port, readiness)
+                .into_values()
+                .map(|endpoint| (K::from_endpoint(&endpoint), endpoint))
+                .collect(),
+        }
+    }
+}
+
+impl<K: DiscoveryKey> EndpointTracker<K> {
+    /// Creates a tracker weighing endpoints by their pods.
+    fn weighted(weights: SharedWeights) -> Self {
+        Self {
+            weights,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the number of candidate endpoints.
+    fn len(&self) -> usize {
+        self.refs.len()
+    }
+
+    /// Returns the number of tracked slices.
+    fn slice_count(&self) -> usize {
+        self.slices.len()
+    }
+
+    /// Returns the number of tracked slices that expose the configured port.
+    fn slices_with_port(&self) -> usize {
+        self.slices.values().filter(|s| s.port.is_some()).count()
+    }
+
+    /// Replaces the state of a slice and returns the resulting actions.
+    fn apply(&mut self, key: String, current: SliceState<K>) -> Vec<EndpointAction<K>> {
+        let current = self.weigh(current);
+        let previous = self.slices.remove(&key).unwrap_or_default();
+        let mut actions = Vec::new();
+
+        // Addresses include the port, so renumbered endpoints are replaced below
+        if previous.port.is_some() && previous.port != current.port {
+            debug!(
+                "port of EndpointSlice {key} changed from {:?} to {:?}",
+                previous.port, current.port
+            );
+        }
+
+        for key in previous.endpoints.keys() {
+            if !current.endpoints.contains_key(key) {
+                self.release(key, &mut actions);
+            }
+        }
+
+        for (key, endpoint) in &current.endpoints {
+            match previous.endpoints.get(key) {
+                None => self.acquire(key, endpoint, &mut actions),
+                Some(known) if known != endpoint => {
+                    actions.push(EndpointAction::Update(
+                        key.clone(),
+                        Box::new(endpoint.clone()),
+                    ));
+                }
+                Some(_) => {}
+            }
+        }
+
+        self.slices.insert(key, current);
+        actions
+    }
+
+    /// Forgets a slice and returns removals for all endpoints it contributed.
+    fn delete(&mut self, key: &str) -> Vec<EndpointAction<K>> {
+        let removed = self.slices.remove(key).unwrap_or_default();
+        let mut actions = Vec::new();
+
+        for key in removed.endpoints.keys() {
+            self.release(key, &mut actions);
+        }
+
+        actions
+    }
+
+    /// Starts buffering slices for a re-list.
+    fn init(&mut self) {
+        self.pending = Some(HashMap::new());
+    }
+
+    /// Buffers a listed slice, or applies it directly if no re-list is in progress.
+    fn init_apply(&mut self, key: String, current: SliceState<K>) -> Vec<EndpointAction<K>> {
+        let current = self.weigh(current);
+        match &mut self.pending {
+            Some(pending) => {
+                pending.insert(key, current);
+                Vec::new()
+            }
+
+            None => self.apply(key, current),
+        }
+    }
+
+    /// Replaces the known state with the buffered slices and returns the net difference.
+    ///
+    /// Endpoints present both before and after the re-list are left untouched, so
+    /// connections to surviving pods are not churned.
+    fn init_done(&mut self) -> Vec<EndpointAction<K>> {
+        let Some(slices) = self.pending.take() else {
+            return Vec::new();
+        };
+
+        let known: HashMap<&K, &DiscoveredEndpoint> =
+            self.slices.values().flat_map(|s| &s.endpoints).collect();
+        let mut refs: HashMap<K, usize> = HashMap::new();
+        let mut actions = Vec::new();
+
+        for (key, endpoint) in slices.values().flat_map(|s| &s.endpoints) {
+            let count = refs.entry(key.clone()).or_default();
+            *count += 1;
+
+            if *count > 1 {
+                continue;
+            }
+
+            if !self.refs.contains_key(key) {
+                debug!("adding endpoint: {key:?}");
+                actions.push(EndpointAction::Insert(
+                    key.clone(),
+                    Box::new(endpoint.clone()),
+                ));
+            } else if known.get(key).is_some_and(|known| *known != endpoint) {
+                actions.push(EndpointAction::Update(
+                    key.clone(),
+                    Box::new(endpoint.clone()),
+                ));
+            }
+        }
+
+        for key in self.refs.keys() {
+            if !refs.contains_key(key) {
+                debug!("removing endpoint: {key:?}");
+                actions.push(EndpointAction::Remove(key.clone()));
+            }
+        }
+
+        self.slices = slices;
+        self.refs = refs;
+        actions
+    }
+
+    /// Re-weighs the endpoints after weights changed.
+    fn reweigh(&mut self) -> Vec<EndpointAction<K>> {
+        if let Some(pending) = self.pending.take() {
+            let pending = pending
+                .into_iter()
+                .map(|(key, state)| (key, self.weigh(state)))
+                .collect();
+
+            self.pending = Some(pending);
+        }
+
+        let keys: Vec<String> = self.slices.keys().cloned().collect();
+        keys.into_iter()
+            .flat_map(|key| {
+                let current = self.slices[&key].clone();
+                self.apply(key, current)
+            })
+            .collect()
+    }
+
+    /// Sets the weights of a slice's endpoints, whose keys may depend on them.
+    fn weigh(&self, state: SliceState<K>) -> SliceState<K> {
+        let weights = self.weights.lock().expect("weights lock");
+        if !weights.enabled() {
+            return state;
+        }
+
+        let endpoints = state
+            .endpoints
+            .into_values()
+            .map(|mut endpoint| {
+                endpoint.weight = weights.weight(&endpoint);
+                (K::from_endpoint(&endpoint), endpoint)
+            })
+            .collect();
+
+        SliceState {
+            port: state.port,
+            endpoints,
+        }
+    }
+
+    /// Records a slice membership, inserting the endpoint if its key is new.
+    fn acquire(
+        &mut self,
+        key: &K,
+        endpoint: &DiscoveredEndpoint,
+        actions: &mut Vec<EndpointAction<K>>,
+    ) {
+        let count = self.refs.entry(key.clone()).or_default();
+        *count += 1;
+
+        if *count == 1 {
+            debug!("adding endpoint: {key:?}");
+            actions.push(EndpointAction::Insert(
+                key.clone(),
+                Box::new(endpoint.clone()),
+            ));
+        }
+    }
+
+    /// Drops a slice membership, removing the endpoint once no slice contains it.
+    fn release(&mut self, key: &K, actions: &mut Vec<EndpointAction<K>>) {
+        let Some(count) = self.refs.get_mut(key) else {
+            return;
+        };
+
+        *count -= 1;
+
+        if *count == 0 {
+            self.refs.remove(key);
+            debug!("removing endpoint: {key:?}");
+            actions.push(EndpointAction::Remove(key.clone()));
+        }
+    }
+}
+
+/// Returns the key identifying an `EndpointSlice` in the tracker.
+///
+/// Prefers the UID, falling back to the namespaced name.
+fn slice_key(slice: &EndpointSlice) -> String {
+    let meta = &slice.metadata;
+    meta.uid.clone().unwrap_or_else(|| {
+        format!(
+            "{}/{}",
+            meta.namespace.as_deref().unwrap_or_default(),
+            meta.name.as_deref().unwrap_or_default()
+        )
+    })
+}
+
+/// Processes a watcher event and returns the endpoint actions.
+///
+/// This function is extracted to enable unit testing of the event processing logic.
+fn process_event<K: DiscoveryKey>(
+    event: &Event<EndpointSlice>,
+    tracker: &mut EndpointTracker<K>,
+    port: &Port,
+    readiness: ReadinessPolicy,
+) -> Vec<EndpointAction<K>> {
+    match event {
+        Event::Apply(slice) => {
+            tracker.apply(slice_key(slice), SliceState::new(slice, port, readiness))
+        }
+
+        Event::InitApply(slice) => {
+            tracker.init_apply(slice_key(slice), SliceState::new(slice, port, readiness))
+        }
+
+        Event::Delete(slice) => tracker.delete(&slice_key(slice)),
+
+        Event::Init => {
+            debug!("Kubernetes watcher (re)initializing");
+            tracker.init();
+            Vec::new()
+        }
+
+        Event::InitDone => {
+            debug!("Kubernetes watcher initialization done");
+            tracker.init_done()
+        }
+    }
+}
+
+/// Resolves the configured port against the ports published by an `EndpointSlice`.
+///
+/// Returns the port number and the matching port entry, if the slice lists one.
+fn resolve_port<'a>(
+    slice: &'a EndpointSlice,
+    port: &Port,
+) -> Option<(u16, Option<&'a EndpointPort>)> {
+    let ports = slice.ports.as_deref().unwrap_or_default();
+
+    let entry = match port {
+        Port::Number(n) => {
+            let listed = ports.iter().find(|p| port_number(p) == Some(*n));
+
+            // A slice listing no ports, or a port without a number, exposes all ports
+            if listed.is_none() && (ports.is_empty() || ports.iter().any(|p| p.port.is_none())) {
+                return Some((*n, None));
+            }
+
+            listed.filter(|p| is_tcp(p))?
+        }
+
+        // Ports derived from an unnamed Service port have an empty name
+        Port::Name(name) => ports
+            .iter()
+            .find(|p| p.name.as_deref().unwrap_or_default() == name)?,
+
+        Port::AppProtocol(app_protocol) => ports
+            .iter()
+            .find(|p| p.app_protocol.as_deref() == Some(app_protocol.as_str()))?,
+
+        Port::Protocol(protocol) => ports
+            .iter()
+            .find(|p| p.protocol.as_deref().unwrap_or("TCP") == protocol)?,
+
+        Port::OnlyTcp => {
+            let mut tcp = ports.iter().filter(|p| is_tcp(p));
+            let only = tcp.next()?;
+
+            if tcp.next().is_some() {
+                return None;
+            }
+
+            only
+        }
+
+        // Mapped to the name of the Service port before watching
+        Port::ServicePort(_) => return None,
+    };
+
+    Some((port_number(entry)?, Some(entry)))
+}
+
+/// Returns the number of an `EndpointSlice` port entry, if valid.
+fn port_number(port: &EndpointPort) -> Option<u16> {
+    port.port.and_then(|n| u16::try_from(n).ok())
+}
+
+/// Returns whether an `EndpointSlice` port entry uses TCP, the default protocol.
+fn is_tcp(port: &EndpointPort) -> bool {
+    port.protocol.as_deref().unwrap_or("TCP") == "TCP"
+}
+
+/// Extracts the endpoints admitted by the readiness policy from an `EndpointSlice`,
+/// keyed by address.
+fn extract_endpoints(
+    slice: &EndpointSlice,
+    port: &Port,
+    readiness: ReadinessPolicy,
+) -> HashMap<SocketAddr, DiscoveredEndpoint> {
+    let Some((port_number, slice_port)) = resolve_port(slice, port) else {
+        return HashMap::new();
+    };
+
+    let mut endpoints = HashMap::new();
+
+    for ep in &slice.endpoints {
+        for addr in &ep.addresses {
+            if let Ok(ip) = addr.parse::<IpAddr>() {
+                let address = SocketAddr::new(ip, port_number);
+                let namespace = slice.metadata.namespace.as_deref();
+                let endpoint = DiscoveredEndpoint::from_slice(ep, namespace, address, slice_port);
+
+                if readiness.admits(&endpoint) {
+                    endpoints.entry(address).or_insert(endpoint);
+                }
+            }
+        }
+    }
+
+    endpoints
+}
+
+/// Creates a client for an API server that cannot be reached.
+#[cfg(test)]
+pub(crate) fn test_client() -> Client {
+    let config = kube::Config::new("http://127.0.0.1:1".parse().unwrap());
+    Client::try_from(config).unwrap()
+}
+
+#[cfg(test)]
 mod tests {
+    use std::collections::VecDeque;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use futures::future::BoxFuture;
+    use k8s_openapi::api::core::v1::{ObjectReference, ServicePort, ServiceSpec};
     use k8s_openapi::api::discovery::v1::{Endpoint, EndpointConditions, EndpointPort};
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+    use kube::core::PartialObjectMeta;
+
+    use super::*;
+    use crate::endpoint::{EndpointKey, Weighted};
+    use crate::retry::ErrorKind;
+    use crate::subset::ClientId;
+    use crate::topology::{NodeSource, ZoneSource};
+
+    // Port conversion tests
+
+    #[test]
+    fn port_from_u16() {
+        let port: Port = 50051_u16.into();
+        assert_eq!(port, Port::Number(50051));
+    }
+
+    #[test]
+    fn port_from_str() {
+        let port: Port = "grpc".into();
+        assert_eq!(port, Port::Name("grpc".to_string()));
+    }
+
+    #[test]
+    fn port_from_string() {
+        let port: Port = String::from("grpc").into();
+        assert_eq!(port, Port::Name("grpc".to_string()));
+    }
+
+    // DiscoveryConfig tests
+
+    #[test]
+    fn config_new_with_numeric_port() {
+        let config = DiscoveryConfig::new("my-service", 50051_u16);
+
+        assert_eq!(config.service_name, "my-service");
+        assert!(config.namespace.is_none());
+        assert_eq!(config.port, Port::Number(50051));
+    }
+
+    #[test]
+    fn config_new_with_named_port() {
+        let config = DiscoveryConfig::new("my-service", "grpc");
+
+        assert_eq!(config.service_name, "my-service");
+        assert!(config.namespace.is_none());
+        assert_eq!(config.port, Port::Name("grpc".to_string()));
+    }
+
+    #[test]
+    fn config_new_uses_default_retry_policy() {
+        let config = DiscoveryConfig::new("my-service", 50051_u16);
+        assert_eq!(config.retry, RetryPolicy::default());
+    }
+
+    #[test]
+    fn config_new_requires_one_endpoint() {
+        let config = DiscoveryConfig::new("my-service", 50051_u16);
+        assert_eq!(config.min_endpoints, 1);
+    }
+
+    #[test]
+    fn config_with_min_endpoints() {
+        let config = DiscoveryConfig::new("my-service", 50051_u16).min_endpoints(3);
+        assert_eq!(config.min_endpoints, 3);
+    }
+
+    #[test]
+    fn config_with_retry_policy() {
+        let retry = RetryPolicy::default()
+            .max_backoff(Duration::from_secs(5))
+            .fatal(ErrorKind::Forbidden);
+
+        let config = DiscoveryConfig::new("my-service", 50051_u16).retry(retry.clone());
+        assert_eq!(config.retry, retry);
+    }
+
+    #[test]
+    fn config_services_starts_with_primary() {
+        let config = DiscoveryConfig::new("svc-a", 50051_u16)
+            .namespace("ns-a")
+            .add_service(ServiceRef::new("svc-b", "grpc"))
+            .add_service(ServiceRef::new("svc-c", 8080_u16).namespace("ns-c"));
+
+        assert_eq!(
+            config.services(),
+            vec![
+                ServiceRef::new("svc-a", 50051_u16).namespace("ns-a"),
+                ServiceRef::new("svc-b", "grpc"),
+                ServiceRef::new("svc-c", 8080_u16).namespace("ns-c"),
+            ]
+        );
+    }
+
+    #[test]
+    fn service_ref_selects_slices_by_service_name() {
+        let config = ServiceRef::new("my-service", 50051_u16).watcher_config();
+
+        assert_eq!(
+            config.label_selector.as_deref(),
+            Some("kubernetes.io/service-name=my-service")
+        );
+        assert_eq!(config.field_selector, None);
+    }
+
+    #[test]
+    fn service_ref_with_custom_selectors() {
+        let config = ServiceRef::new("my-service", 50051_u16)
+            .label_selector("app.kubernetes.io/part-of=payments")
+            .field_selector("metadata.name!=legacy")
+            .watcher_config();
+
+        assert_eq!(
+            config.label_selector.as_deref(),
+            Some("app.kubernetes.io/part-of=payments")
+        );
+        assert_eq!(
+            config.field_selector.as_deref(),
+            Some("metadata.name!=legacy")
+        );
+    }
+
+    #[test]
+    fn config_selectors_apply_to_primary_service() {
+        let config = DiscoveryConfig::new("my-service", 50051_u16)
+            .label_selector("app=my-app")
+            .field_selector("metadata.name=my-slice");
+
+        let primary = &config.services()[0];
+        assert_eq!(primary.label_selector.as_deref(), Some("app=my-app"));
+        assert_eq!(
+            primary.field_selector.as_deref(),
+            Some("metadata.name=my-slice")
+        );
+    }
+
+    // NamespaceFilter tests
+
+    fn slice_in(namespace: &str) -> EndpointSlice {
+        EndpointSlice {
+            metadata: ObjectMeta {
+                name: Some("svc-a".to_string()),
+                namespace: Some(namespace.to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn namespace_filter_default_allows_everything() {
+        let filter = NamespaceFilter::default();
+
+        assert!(filter.allows("default"));
+        assert!(filter.allows("tenant-a"));
+    }
+
+    #[test]
+    fn namespace_filter_allow_list() {
+        let filter = NamespaceFilter::default()
+            .allow("tenant-a")
+            .allow("tenant-b");
+
+        assert!(filter.allows("tenant-a"));
+        assert!(filter.allows("tenant-b"));
+        assert!(!filter.allows("tenant-c"));
+    }
+
+    #[test]
+    fn namespace_filter_deny_wins_over_allow() {
+        let filter = NamespaceFilter::default()
+            .allow("tenant-a")
+            .deny("tenant-a")
+            .deny("kube-system");
+
+        assert!(!filter.allows("tenant-a"));
+        assert!(!filter.allows("kube-system"));
+    }
+
+    #[test]
+    fn namespace_filter_admits_events() {
+        let filter = NamespaceFilter::default().deny("kube-system");
+
+        assert!(filter.admits(&Event::Apply(slice_in("tenant-a"))));
+        assert!(!filter.admits(&Event::InitApply(slice_in("kube-system"))));
+        assert!(!filter.admits(&Event::Delete(slice_in("kube-system"))));
+        assert!(filter.admits(&Event::Init));
+        assert!(filter.admits(&Event::InitDone));
+    }
+
+    #[test]
+    fn config_all_namespaces_applies_to_primary_service() {
+        let filter = NamespaceFilter::default().allow("tenant-a");
+        let config = DiscoveryConfig::new("my-service", 50051_u16).all_namespaces(filter.clone());
+
+        assert_eq!(config.services()[0].all_namespaces, Some(filter));
+    }
+
+    #[test]
+    fn config_with_readiness_policy() {
+        let config = DiscoveryConfig::new("my-service", 50051_u16);
+        assert_eq!(config.readiness, ReadinessPolicy::Ready);
+
+        let config = config.readiness(ReadinessPolicy::TerminatingFallback);
+        assert_eq!(config.readiness, ReadinessPolicy::TerminatingFallback);
+    }
+
+    #[test]
+    fn config_with_optional_features() {
+        let config = DiscoveryConfig::new("my-service", 50051_u16);
+        assert!(config.zone_aware.is_none());
+        assert!(config.node_local.is_none());
+        assert!(config.weights.is_none());
+        assert!(config.subset.is_none());
+        assert!(config.settle_window.is_none());
+        assert!(config.panic_threshold.is_none());
+
+        let zone_aware = ZoneAware::new(ZoneSource::Zone("us-east-1a".to_string()));
+        let node_local = NodeLocal::only(NodeSource::default());
+        let weights = WeightSource::Label("weight".to_string());
+        let subset = Subset::new(10).client_id(ClientId::Name("client-1".to_string()));
+        let threshold = PanicThreshold::default().min_endpoints(2);
+        let config = config
+            .zone_aware(zone_aware.clone())
+            .node_local(node_local.clone())
+            .weights(weights.clone())
+            .subset(subset.clone())
+            .settle_window(Duration::from_millis(500))
+            .panic_threshold(threshold.clone());
+
+        assert_eq!(config.zone_aware, Some(zone_aware));
+        assert_eq!(config.node_local, Some(node_local));
+        assert_eq!(config.weights, Some(weights));
+        assert_eq!(config.subset, Some(subset));
+        assert_eq!(config.settle_window, Some(Duration::from_millis(500)));
+        assert_eq!(config.panic_threshold, Some(threshold));
+    }
+
+    // API server denying every request
+    struct Deny;
+
+    impl tower::Service<http::Request<kube::client::Body>> for Deny {
+        type Response = http::Response<kube::client::Body>;
+        type Error = Infallible;
+        type Future = future::Ready<std::result::Result<Self::Response, Infallible>>;
+
+        fn poll_ready(
+            &mut self,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::result::Result<(), Infallible>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _request: http::Request<kube::client::Body>) -> Self::Future {
+            let status = r#"{"kind":"Status","apiVersion":"v1","status":"Failure","reason":"Forbidden","message":"forbidden","code":403}"#;
+            let response = http::Response::builder()
+                .status(403)
+                .header("content-type", "application/json")
+                .body(kube::client::Body::from(status.as_bytes().to_vec()))
+                .unwrap();
+
+            future::ready(Ok(response))
+        }
+    }
+
+    fn node_zone_aware() -> DiscoveryConfig {
+        let node = NodeSource::Name("node-1".to_string());
+        DiscoveryConfig::new("my-service", 50051_u16)
+            .zone_aware(ZoneAware::new(ZoneSource::Node(node)))
+    }
+
+    #[tokio::test]
+    async fn zone_preference_without_permission_uses_all_zones() {
+        // Not retried, even though the default policy retries forever
+        let config = node_zone_aware();
+        let client = Client::new(Deny, "default");
+        let zone = zone_preference(&config, &client);
+
+        let zone = tokio::time::timeout(Duration::from_secs(5), zone)
+            .await
+            .unwrap();
+        assert!(zone.is_none());
+    }
+
+    #[tokio::test]
+    async fn zone_preference_gives_up_on_unreachable_api_server() {
+        let config = node_zone_aware().retry(
+            RetryPolicy::default()
+                .initial_backoff(Duration::from_millis(1))
+                .max_failures(2),
+        );
+
+        let client = test_client();
+        let zone = zone_preference(&config, &client);
+        let zone = tokio::time::timeout(Duration::from_secs(5), zone)
+            .await
+            .unwrap();
+        assert!(zone.is_none());
+    }
+
+    #[tokio::test]
+    async fn zone_preference_reads_static_zone() {
+        let config = DiscoveryConfig::new("my-service", 50051_u16)
+            .zone_aware(ZoneAware::new(ZoneSource::Zone("us-east-1a".to_string())));
+
+        let zone = zone_preference(&config, &test_client()).await.unwrap();
+        assert_eq!(zone.zone, "us-east-1a");
+    }
+
+    #[test]
+    fn subsetter_uses_client_id() {
+        let subset = Subset::new(10).client_id(ClientId::Name("client-1".to_string()));
+        assert_eq!(subsetter(&subset).client, "client-1");
+
+        // Unknown identities are replaced by a random one
+        let subset =
+            Subset::new(10).client_id(ClientId::Env("TONIC_LB_K8S_TEST_UNSET_CLIENT".to_string()));
+        assert_eq!(subsetter(&subset).client.len(), 16);
+    }
+
+    #[test]
+    fn config_new_has_no_client() {
+        let config = DiscoveryConfig::new("my-service", 50051_u16);
+        assert!(config.client.is_none());
+    }
+
+    #[tokio::test]
+    async fn config_with_client() {
+        let config = DiscoveryConfig::new("my-service", 50051_u16).client(test_client());
+
+        assert!(config.client.is_some());
+        assert!(format!("{config:?}").contains("client: Some(\"Client\")"));
+    }
+
+    #[tokio::test]
+    async fn discover_uses_supplied_client() {
+        // The supplied client points at an unreachable API server, so the watch fails
+        // with a client error instead of failing to infer a configuration
+        let config = DiscoveryConfig::new("my-service", 50051_u16)
+            .client(test_client())
+            .retry(RetryPolicy::default().max_failures(1));
+
+        let (tx, _rx) = tokio::sync::mpsc::channel::<Change<SocketAddr, _>>(1);
+        let handle = discover(config, tx, |endpoint| {
+            tonic::transport::Endpoint::from_shared(format!("http://{}", endpoint.address)).unwrap()
+        });
+
+        let err = handle.join().await.unwrap_err();
+        assert!(matches!(err, Error::Kube(_)), "unexpected error: {err}");
+    }
+
+    #[tokio::test]
+    async fn discover_fails_without_node_name_for_node_local_only() {
+        // Not retried, even though the default policy retries forever
+        let node = NodeSource::Env("TONIC_LB_K8S_TEST_UNSET_NODE".to_string());
+        let config = DiscoveryConfig::new("my-service", 50051_u16)
+            .client(test_client())
+            .node_local(NodeLocal::only(node));
+
+        let (tx, _rx) = tokio::sync::mpsc::channel::<Change<SocketAddr, _>>(1);
+        let handle = discover(config, tx, |endpoint| {
+            tonic::transport::Endpoint::from_shared(format!("http://{}", endpoint.address)).unwrap()
+        });
+
+        let err = tokio::time::timeout(Duration::from_secs(5), handle.join())
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert!(matches!(err, Error::UnknownNode), "unexpected error: {err}");
+    }
+
+    #[test]
+    fn config_with_namespace() {
+        let config = DiscoveryConfig::new("my-service", 50051_u16).namespace("my-namespace");
+
+        assert_eq!(config.service_name, "my-service");
+        assert_eq!(config.namespace, Some("my-namespace".to_string()));
+        assert_eq!(config.port, Port::Number(50051));
+    }
+
+    // Helper to create an endpoint with addresses and optional ready condition
+    fn make_endpoint(addresses: Vec<&str>, ready: Option<bool>) -> Endpoint {
+        Endpoint {
+            addresses: addresses.into_iter().map(String::from).collect(),
+            conditions: Some(EndpointConditions {
+                ready,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    // Helper to create an endpoint port
+    fn make_port(name: Option<&str>, port: i32) -> EndpointPort {
+        EndpointPort {
+            name: name.map(String::from),
+            port: Some(port),
+            ..Default::default()
+        }
+    }
+
+    // resolve_port tests
+
+    fn make_slice_with_ports(ports: Vec<EndpointPort>) -> EndpointSlice {
+        EndpointSlice {
+            ports: Some(ports),
+            ..Default::default()
+        }
+    }
+
+    fn make_typed_port(
+        name: &str,
+        port: i32,
+        protocol: Option<&str>,
+        app_protocol: Option<&str>,
+    ) -> EndpointPort {
+        EndpointPort {
+            protocol: protocol.map(String::from),
+            app_protocol: app_protocol.map(String::from),
+            ..make_port(Some(name), port)
+        }
+    }
+
+    fn resolved_number(slice: &EndpointSlice, port: &Port) -> Option<u16> {
+        resolve_port(slice, port).map(|(number, _)| number)
+    }
+
+    #[test]
+    fn resolve_port_number_must_be_published() {
+        let slice = make_slice_with_ports(vec![make_port(Some("grpc"), 50051)]);
+
+        assert_eq!(resolved_number(&slice, &Port::Number(50051)), Some(50051));
+        assert_eq!(resolved_number(&slice, &Port::Number(8080)), None);
+    }
+
+    #[test]
+    fn resolve_port_number_must_be_tcp() {
+        let slice = make_slice_with_ports(vec![make_typed_port("dns", 53, Some("UDP"), None)]);
+        assert_eq!(resolved_number(&slice, &Port::Number(53)), None);
+    }
+
+    #[test]
+    fn resolve_port_number_without_listed_ports() {
+        // Slices listing no ports, or a port without a number, expose all ports
+        let slice = EndpointSlice::default();
+        assert_eq!(resolved_number(&slice, &Port::Number(50051)), Some(50051));
+
+        let slice = make_slice_with_ports(vec![EndpointPort::default()]);
+        assert_eq!(resolved_number(&slice, &Port::Number(50051)), Some(50051));
+    }
+
+    #[test]
+    fn resolve_port_by_app_protocol() {
+        let slice = make_slice_with_ports(vec![
+            make_typed_port("http", 8080, None, Some("http")),
+            make_typed_port("rpc", 9090, None, Some("kubernetes.io/h2c")),
+        ]);
+
+        let port = Port::AppProtocol("kubernetes.io/h2c".to_string());
+        let (number, entry) = resolve_port(&slice, &port).unwrap();
+
+        assert_eq!(number, 9090);
+        assert_eq!(entry.and_then(|p| p.name.as_deref()), Some("rpc"));
+        assert_eq!(
+            resolved_number(&slice, &Port::AppProtocol("grpc".to_string())),
+            None
+        );
+    }
+
+    #[test]
+    fn resolve_port_by_protocol() {
+        let slice = make_slice_with_ports(vec![
+            make_typed_port("dns", 53, Some("UDP"), None),
+            make_typed_port("grpc", 50051, None, None),
+        ]);
+
+        assert_eq!(
+            resolved_number(&slice, &Port::Protocol("UDP".to_string())),
+            Some(53)
+        );
+        assert_eq!(
+            resolved_number(&slice, &Port::Protocol("TCP".to_string())),
+            Some(50051)
+        );
+        assert_eq!(
+            resolved_number(&slice, &Port::Protocol("SCTP".to_string())),
+            None
+        );
+    }
+
+    #[test]
+    fn resolve_port_only_tcp() {
+        let slice = make_slice_with_ports(vec![
+            make_typed_port("dns", 53, Some("UDP"), None),
+            make_typed_port("grpc", 50051, Some("TCP"), None),
+        ]);
+        assert_eq!(resolved_number(&slice, &Port::OnlyTcp), Some(50051));
+
+        // Ambiguous with several TCP ports
+        let slice = make_slice_with_ports(vec![
+            make_port(Some("grpc"), 50051),
+            make_port(Some("metrics"), 9100),
+        ]);
+        assert_eq!(resolved_number(&slice, &Port::OnlyTcp), None);
+
+        assert_eq!(
+            resolved_number(&EndpointSlice::default(), &Port::OnlyTcp),
+            None
+        );
+    }
+
+    // Service port mapping tests
+
+    fn make_service(ports: Vec<(Option<&str>, i32)>) -> Service {
+        Service {
+            spec: Some(ServiceSpec {
+                ports: Some(
+                    ports
+                        .into_iter()
+                        .map(|(name, port)| ServicePort {
+                            name: name.map(String::from),
+                            port,
+                            ..Default::default()
+                        })
+                        .collect(),
+                ),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn service_port_name_finds_port_by_number() {
+        let service = make_service(vec![(Some("http"), 80), (Some("grpc"), 443)]);
+
+        assert_eq!(service_port_name(&service, 443).as_deref(), Some("grpc"));
+        assert_eq!(service_port_name(&service, 8080), None);
+    }
+
+    #[test]
+    fn service_port_name_unnamed_port_is_empty() {
+        let service = make_service(vec![(None, 80)]);
+        assert_eq!(service_port_name(&service, 80).as_deref(), Some(""));
+    }
+
+    #[test]
+    fn service_port_name_without_spec() {
+        assert_eq!(service_port_name(&Service::default(), 80), None);
+    }
+
+    #[test]
+    fn resolve_port_empty_name_matches_unnamed_port() {
+        // Slices derived from an unnamed Service port may omit the name
+        let slice = make_slice_with_ports(vec![make_port(None, 50051)]);
+        assert_eq!(
+            resolved_number(&slice, &Port::Name(String::new())),
+            Some(50051)
+        );
+    }
+
+    #[test]
+    fn resolve_port_service_port_is_not_resolved_directly() {
+        let slice = make_slice_with_ports(vec![make_port(Some("grpc"), 50051)]);
+        assert_eq!(resolved_number(&slice, &Port::ServicePort(50051)), None);
+    }
+
+    #[tokio::test]
+    async fn map_service_port_keeps_other_ports() {
+        let port = Port::Number(50051);
+        let mapped = map_service_port(&test_client(), "default", "my-service", &port).await;
+
+        assert_eq!(mapped.unwrap(), port);
+    }
+
+    // extract_endpoints tests
+
+    #[test]
+    fn extract_endpoints_empty_slice() {
+        let slice = EndpointSlice {
+            endpoints: Vec::new(),
+            ..Default::default()
+        };
+
+        let addrs = extract_endpoints(&slice, &Port::Number(50051), ReadinessPolicy::Ready);
+        assert!(addrs.is_empty());
+    }
+
+    #[test]
+    fn extract_endpoints_with_numeric_port() {
+        let slice = EndpointSlice {
+            endpoints: vec![make_endpoint(vec!["10.0.0.1", "10.0.0.2"], Some(true))],
+            ..Default::default()
+        };
+
+        let addrs = extract_endpoints(&slice, &Port::Number(50051), ReadinessPolicy::Ready);
+
+        assert_eq!(addrs.len(), 2);
+        assert!(addrs.contains_key(&"10.0.0.1:50051".parse().unwrap()));
+        assert!(addrs.contains_key(&"10.0.0.2:50051".parse().unwrap()));
+    }
+
+    #[test]
+    fn extract_endpoints_with_named_port() {
+        let slice = EndpointSlice {
+            endpoints: vec![make_endpoint(vec!["10.0.0.1"], Some(true))],
+            ports: Some(vec![make_port(Some("grpc"), 9090)]),
+            ..Default::default()
+        };
+
+        let addrs = extract_endpoints(
+            &slice,
+            &Port::Name("grpc".to_string()),
+            ReadinessPolicy::Ready,
+        );
+
+        assert_eq!(addrs.len(), 1);
+        assert!(addrs.contains_key(&"10.0.0.1:9090".parse().unwrap()));
+    }
+
+    #[test]
+    fn extract_endpoints_named_port_not_found() {
+        let slice = EndpointSlice {
+            endpoints: vec![make_endpoint(vec!["10.0.0.1"], Some(true))],
+            ports: Some(vec![make_port(Some("http"), 8080)]),
+            ..Default::default()
+        };
+
+        let addrs = extract_endpoints(
+            &slice,
+            &Port::Name("grpc".to_string()),
+            ReadinessPolicy::Ready,
+        );
+        assert!(addrs.is_empty());
+    }
+
+    #[test]
+    fn extract_endpoints_named_port_no_ports_defined() {
+        let slice = EndpointSlice {
+            endpoints: vec![make_endpoint(vec!["10.0.0.1"], Some(true))],
+            ports: None,
+            ..Default::default()
+        };
+
+        let addrs = extract_endpoints(
+            &slice,
+            &Port::Name("grpc".to_string()),
+            ReadinessPolicy::Ready,
+        );
+        assert!(addrs.is_empty());
+    }
+
+    #[test]
+    fn extract_endpoints_skips_not_ready() {
+        let slice = EndpointSlice {
+            endpoints: vec![
+                make_endpoint(vec!["10.0.0.1"], Some(true)),
+                make_endpoint(vec!["10.0.0.2"], Some(false)),
+            ],
+            ..Default::default()
+        };
+
+        let addrs = extract_endpoints(&slice, &Port::Number(50051), ReadinessPolicy::Ready);
+
+        assert_eq!(addrs.len(), 1);
+        assert!(addrs.contains_key(&"10.0.0.1:50051".parse().unwrap()));
+    }
+
+    #[test]
+    fn extract_endpoints_serving_includes_terminating() {
+        let terminating = Endpoint {
+            addresses: vec!["10.0.0.2".to_string()],
+            conditions: Some(EndpointConditions {
+                ready: Some(false),
+                serving: Some(true),
+                terminating: Some(true),
+            }),
+            ..Default::default()
+        };
+
+        let slice = EndpointSlice {
+            endpoints: vec![
+                make_endpoint(vec!["10.0.0.1"], Some(true)),
+                terminating,
+                make_endpoint(vec!["10.0.0.3"], Some(false)),
+            ],
+            ..Default::default()
+        };
+
+        let addrs = extract_endpoints(&slice, &Port::Number(50051), ReadinessPolicy::Serving);
+        assert_eq!(addrs.len(), 2);
+        assert!(addrs[&"10.0.0.2:50051".parse().unwrap()].terminating);
+
+        let addrs = extract_endpoints(&slice, &Port::Number(50051), ReadinessPolicy::All);
+        assert_eq!(addrs.len(), 3);
+    }
+
+    #[test]
+    fn extract_endpoints_ready_defaults_to_true() {
+        // When ready is None, it should default to true
+        let slice = EndpointSlice {
+            endpoints: vec![Endpoint {
+                addresses: vec!["10.0.0.1".to_string()],
+                conditions: Some(EndpointConditions {
+                    ready: None,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let addrs = extract_endpoints(&slice, &Port::Number(50051), ReadinessPolicy::Ready);
+
+        assert_eq!(addrs.len(), 1);
+        assert!(addrs.contains_key(&"10.0.0.1:50051".parse().unwrap()));
+    }
+
+    #[test]
+    fn extract_endpoints_no_conditions_defaults_to_ready() {
+        // When conditions is None entirely, should default to ready
+        let slice = EndpointSlice {
+            endpoints: vec![Endpoint {
+                addresses: vec!["10.0.0.1".to_string()],
+                conditions: None,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let addrs = extract_endpoints(&slice, &Port::Number(50051), ReadinessPolicy::Ready);
+
+        assert_eq!(addrs.len(), 1);
+        assert!(addrs.contains_key(&"10.0.0.1:50051".parse().unwrap()));
+    }
+
+    #[test]
+    fn extract_endpoints_skips_invalid_ip() {
+        let slice = EndpointSlice {
+            endpoints: vec![Endpoint {
+                addresses: vec!["not-an-ip".to_string(), "10.0.0.1".to_string()],
+                conditions: Some(EndpointConditions {
+                    ready: Some(true),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let addrs = extract_endpoints(&slice, &Port::Number(50051), ReadinessPolicy::Ready);
+
+        assert_eq!(addrs.len(), 1);
+        assert!(addrs.contains_key(&"10.0.0.1:50051".parse().unwrap()));
+    }
+
+    #[test]
+    fn extract_endpoints_ipv6() {
+        let slice = EndpointSlice {
+            endpoints: vec![make_endpoint(vec!["::1", "2001:db8::1"], Some(true))],
+            ..Default::default()
+        };
+
+        let addrs = extract_endpoints(&slice, &Port::Number(50051), ReadinessPolicy::Ready);
+
+        assert_eq!(addrs.len(), 2);
+        assert!(addrs.contains_key(&"[::1]:50051".parse().unwrap()));
+        assert!(addrs.contains_key(&"[2001:db8::1]:50051".parse().unwrap()));
+    }
+
+    #[test]
+    fn extract_endpoints_multiple_endpoints() {
+        let slice = EndpointSlice {
+            endpoints: vec![
+                make_endpoint(vec!["10.0.0.1"], Some(true)),
+                make_endpoint(vec!["10.0.0.2"], Some(true)),
+                make_endpoint(vec!["10.0.0.3"], Some(true)),
+            ],
+            ..Default::default()
+        };
+
+        let addrs = extract_endpoints(&slice, &Port::Number(50051), ReadinessPolicy::Ready);
+
+        assert_eq!(addrs.len(), 3);
+        assert!(addrs.contains_key(&"10.0.0.1:50051".parse().unwrap()));
+        assert!(addrs.contains_key(&"10.0.0.2:50051".parse().unwrap()));
+        assert!(addrs.contains_key(&"10.0.0.3:50051".parse().unwrap()));
+    }
+
+    #[test]
+    fn extract_endpoints_deduplicates_addresses() {
+        // Same address in multiple endpoints should only appear once
+        let slice = EndpointSlice {
+            endpoints: vec![
+                make_endpoint(vec!["10.0.0.1"], Some(true)),
+                make_endpoint(vec!["10.0.0.1"], Some(true)),
+            ],
+            ..Default::default()
+        };
+
+        let addrs = extract_endpoints(&slice, &Port::Number(50051), ReadinessPolicy::Ready);
+
+        assert_eq!(addrs.len(), 1);
+        assert!(addrs.contains_key(&"10.0.0.1:50051".parse().unwrap()));
+    }
+
+    #[test]
+    fn extract_endpoints_multiple_ports_finds_correct_one() {
+        let slice = EndpointSlice {
+            endpoints: vec![make_endpoint(vec!["10.0.0.1"], Some(true))],
+            ports: Some(vec![
+                make_port(Some("http"), 8080),
+                make_port(Some("grpc"), 9090),
+                make_port(Some("metrics"), 9100),
+            ]),
+            ..Default::default()
+        };
 
-    use super::*;
+        let addrs = extract_endpoints(
+            &slice,
+            &Port::Name("grpc".to_string()),
+            ReadinessPolicy::Ready,
+        );
 
-    // Port conversion tests
+        assert_eq!(addrs.len(), 1);
+        assert!(addrs.contains_key(&"10.0.0.1:9090".parse().unwrap()));
+    }
 
     #[test]
-    fn port_from_u16() {
-        let port: Port = 50051_u16.into();
-        assert_eq!(port, Port::Number(50051));
+    fn extract_endpoints_includes_metadata() {
+        let mut endpoint = make_endpoint(vec!["10.0.0.1"], Some(true));
+        endpoint.node_name = Some("node-1".to_string());
+        endpoint.zone = Some("us-east-1a".to_string());
+
+        let slice = EndpointSlice {
+            metadata: ObjectMeta {
+                namespace: Some("tenant-a".to_string()),
+                ..Default::default()
+            },
+            endpoints: vec![endpoint],
+            ports: Some(vec![make_port(Some("grpc"), 9090)]),
+            ..Default::default()
+        };
+
+        // The port name is looked up even when the port is configured by number
+        let addrs = extract_endpoints(&slice, &Port::Number(9090), ReadinessPolicy::Ready);
+        let discovered = &addrs[&"10.0.0.1:9090".parse().unwrap()];
+
+        assert_eq!(discovered.namespace.as_deref(), Some("tenant-a"));
+        assert_eq!(discovered.node_name.as_deref(), Some("node-1"));
+        assert_eq!(discovered.zone.as_deref(), Some("us-east-1a"));
+        assert_eq!(discovered.port_name.as_deref(), Some("grpc"));
+    }
+
+    // process_event tests
+
+    // Inserts an endpoint of a slice in the default namespace
+    fn insert(addr: &str) -> EndpointAction<SocketAddr> {
+        let addr = addr.parse().unwrap();
+        let mut endpoint = DiscoveredEndpoint::new(addr);
+        endpoint.namespace = Some("default".to_string());
+        EndpointAction::Insert(addr, Box::new(endpoint))
+    }
+
+    fn remove(addr: &str) -> EndpointAction<SocketAddr> {
+        EndpointAction::Remove(addr.parse().unwrap())
+    }
+
+    // Renders actions by key only, ignoring endpoint metadata
+    fn render(actions: &[EndpointAction<SocketAddr>]) -> Vec<String> {
+        actions
+            .iter()
+            .map(|action| match action {
+                EndpointAction::Insert(key, _) => format!("insert {key}"),
+                EndpointAction::Update(key, _) => format!("update {key}"),
+                EndpointAction::Remove(key) => format!("remove {key}"),
+            })
+            .collect()
+    }
+
+    // Helper to create a named slice with the given endpoints
+    fn make_slice(name: &str, endpoints: Vec<Endpoint>) -> EndpointSlice {
+        EndpointSlice {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                namespace: Some("default".to_string()),
+                ..Default::default()
+            },
+            endpoints,
+            ..Default::default()
+        }
     }
 
     #[test]
-    fn port_from_str() {
-        let port: Port = "grpc".into();
-        assert_eq!(port, Port::Name("grpc".to_string()));
+    fn process_event_apply_inserts_new_endpoints() {
+        let slice = make_slice(
+            "svc-a",
+            vec![make_endpoint(vec!["10.0.0.1", "10.0.0.2"], Some(true))],
+        );
+
+        let mut tracker = EndpointTracker::<SocketAddr>::default();
+        let actions = process_event(
+            &Event::Apply(slice),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+
+        assert_eq!(actions.len(), 2);
+        assert!(actions.contains(&insert("10.0.0.1:50051")));
+        assert!(actions.contains(&insert("10.0.0.2:50051")));
+        assert_eq!(tracker.len(), 2);
+    }
+
+    #[test]
+    fn process_event_apply_skips_known_endpoints() {
+        let mut tracker = EndpointTracker::<SocketAddr>::default();
+        let slice = make_slice("svc-a", vec![make_endpoint(vec!["10.0.0.1"], Some(true))]);
+        process_event(
+            &Event::Apply(slice),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+
+        let slice = make_slice(
+            "svc-a",
+            vec![make_endpoint(vec!["10.0.0.1", "10.0.0.2"], Some(true))],
+        );
+
+        let actions = process_event(
+            &Event::Apply(slice),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+
+        // Only 10.0.0.2 should be inserted since 10.0.0.1 is already known
+        assert_eq!(actions, vec![insert("10.0.0.2:50051")]);
+        assert_eq!(tracker.len(), 2);
+    }
+
+    #[test]
+    fn process_event_init_apply_inserts_endpoints() {
+        let slice = make_slice("svc-a", vec![make_endpoint(vec!["10.0.0.1"], Some(true))]);
+
+        let mut tracker = EndpointTracker::<SocketAddr>::default();
+        let actions = process_event(
+            &Event::InitApply(slice),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+
+        assert_eq!(actions.len(), 1);
+        assert!(actions.contains(&insert("10.0.0.1:50051")));
+    }
+
+    #[test]
+    fn process_event_apply_removes_endpoint_that_became_not_ready() {
+        let mut tracker = EndpointTracker::<SocketAddr>::default();
+        let slice = make_slice(
+            "svc-a",
+            vec![
+                make_endpoint(vec!["10.0.0.1"], Some(true)),
+                make_endpoint(vec!["10.0.0.2"], Some(true)),
+            ],
+        );
+
+        process_event(
+            &Event::Apply(slice),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+
+        let slice = make_slice(
+            "svc-a",
+            vec![
+                make_endpoint(vec!["10.0.0.1"], Some(true)),
+                make_endpoint(vec!["10.0.0.2"], Some(false)),
+            ],
+        );
+
+        let actions = process_event(
+            &Event::Apply(slice),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+
+        assert_eq!(actions, vec![remove("10.0.0.2:50051")]);
+        assert_eq!(tracker.len(), 1);
+    }
+
+    #[test]
+    fn process_event_apply_reinserts_endpoint_that_became_ready_again() {
+        let mut tracker = EndpointTracker::<SocketAddr>::default();
+        let not_ready = make_slice("svc-a", vec![make_endpoint(vec!["10.0.0.1"], Some(false))]);
+        let ready = make_slice("svc-a", vec![make_endpoint(vec!["10.0.0.1"], Some(true))]);
+
+        process_event(
+            &Event::Apply(ready.clone()),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        let actions = process_event(
+            &Event::Apply(not_ready),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        assert_eq!(actions, vec![remove("10.0.0.1:50051")]);
+
+        let actions = process_event(
+            &Event::Apply(ready),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        assert_eq!(actions, vec![insert("10.0.0.1:50051")]);
+        assert_eq!(tracker.len(), 1);
+    }
+
+    #[test]
+    fn process_event_endpoint_key_replaces_reused_address() {
+        let mut tracker = EndpointTracker::<EndpointKey>::default();
+        let pod = |name: &str| {
+            let mut endpoint = make_endpoint(vec!["10.0.0.1"], Some(true));
+            endpoint.target_ref = Some(ObjectReference {
+                kind: Some("Pod".to_string()),
+                name: Some(name.to_string()),
+                uid: Some(format!("{name}-uid")),
+                ..Default::default()
+            });
+            endpoint
+        };
+
+        let old = make_slice("svc-a", vec![pod("old-pod")]);
+        let new = make_slice("svc-a", vec![pod("new-pod")]);
+
+        process_event(
+            &Event::Apply(old),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        let actions = process_event(
+            &Event::Apply(new),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+
+        // The address is unchanged, but the pod behind it is not
+        let [
+            EndpointAction::Remove(removed),
+            EndpointAction::Insert(inserted, endpoint),
+        ] = actions.as_slice()
+        else {
+            panic!("expected a remove followed by an insert, got {actions:?}");
+        };
+
+        assert_eq!(removed.address, inserted.address);
+        assert_eq!(endpoint.pod_name(), Some("new-pod"));
+        assert_eq!(tracker.len(), 1);
+    }
+
+    #[test]
+    fn process_event_apply_updates_endpoint_that_started_terminating() {
+        let mut tracker = EndpointTracker::<SocketAddr>::default();
+        let serving = make_slice("svc-a", vec![make_endpoint(vec!["10.0.0.1"], Some(true))]);
+        let mut terminating = serving.clone();
+        terminating.endpoints[0].conditions = Some(EndpointConditions {
+            ready: Some(false),
+            serving: Some(true),
+            terminating: Some(true),
+        });
+
+        let port = Port::Number(50051);
+        process_event(
+            &Event::Apply(serving),
+            &mut tracker,
+            &port,
+            ReadinessPolicy::Serving,
+        );
+        let actions = process_event(
+            &Event::Apply(terminating),
+            &mut tracker,
+            &port,
+            ReadinessPolicy::Serving,
+        );
+
+        let [EndpointAction::Update(_, endpoint)] = actions.as_slice() else {
+            panic!("expected an update, got {actions:?}");
+        };
+
+        assert!(endpoint.terminating);
+        assert_eq!(tracker.len(), 1);
+    }
+
+    #[test]
+    fn process_event_apply_removes_scaled_down_endpoints() {
+        let mut tracker = EndpointTracker::<SocketAddr>::default();
+        let slice = make_slice(
+            "svc-a",
+            vec![make_endpoint(
+                vec!["10.0.0.1", "10.0.0.2", "10.0.0.3"],
+                Some(true),
+            )],
+        );
+
+        process_event(
+            &Event::Apply(slice),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+
+        let slice = make_slice("svc-a", vec![make_endpoint(vec!["10.0.0.1"], Some(true))]);
+        let actions = process_event(
+            &Event::Apply(slice),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+
+        assert_eq!(actions.len(), 2);
+        assert!(actions.contains(&remove("10.0.0.2:50051")));
+        assert!(actions.contains(&remove("10.0.0.3:50051")));
+        assert_eq!(tracker.len(), 1);
+    }
+
+    #[test]
+    fn process_event_apply_leaves_other_slices_untouched() {
+        let mut tracker = EndpointTracker::<SocketAddr>::default();
+        let a = make_slice("svc-a", vec![make_endpoint(vec!["10.0.0.1"], Some(true))]);
+        let b = make_slice("svc-b", vec![make_endpoint(vec!["10.0.0.2"], Some(true))]);
+
+        process_event(
+            &Event::Apply(a),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        process_event(
+            &Event::Apply(b),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+
+        // Emptying slice b must not affect endpoints contributed by slice a
+        let b = make_slice("svc-b", Vec::new());
+        let actions = process_event(
+            &Event::Apply(b),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+
+        assert_eq!(actions, vec![remove("10.0.0.2:50051")]);
+        assert_eq!(tracker.len(), 1);
+    }
+
+    #[test]
+    fn process_event_address_in_two_slices_survives_one_delete() {
+        let mut tracker = EndpointTracker::<SocketAddr>::default();
+        let a = make_slice("svc-a", vec![make_endpoint(vec!["10.0.0.1"], Some(true))]);
+        let b = make_slice("svc-b", vec![make_endpoint(vec!["10.0.0.1"], Some(true))]);
+
+        let actions = process_event(
+            &Event::Apply(a.clone()),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        assert_eq!(actions, vec![insert("10.0.0.1:50051")]);
+
+        // The second slice containing the same address must not insert it again
+        let actions = process_event(
+            &Event::Apply(b),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        assert!(actions.is_empty());
+
+        // Deleting one slice keeps the address alive through the other
+        let actions = process_event(
+            &Event::Delete(a),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        assert!(actions.is_empty());
+        assert_eq!(tracker.len(), 1);
+    }
+
+    #[test]
+    fn process_event_rebalancing_between_slices_keeps_endpoint() {
+        let mut tracker = EndpointTracker::<SocketAddr>::default();
+        let a = make_slice(
+            "svc-a",
+            vec![make_endpoint(vec!["10.0.0.1", "10.0.0.2"], Some(true))],
+        );
+
+        process_event(
+            &Event::Apply(a),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+
+        // The controller first adds 10.0.0.2 to slice b, then drops it from slice a
+        let b = make_slice("svc-b", vec![make_endpoint(vec!["10.0.0.2"], Some(true))]);
+        let actions = process_event(
+            &Event::Apply(b.clone()),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        assert!(actions.is_empty());
+
+        let a = make_slice("svc-a", vec![make_endpoint(vec!["10.0.0.1"], Some(true))]);
+        let actions = process_event(
+            &Event::Apply(a),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        assert!(actions.is_empty());
+        assert_eq!(tracker.len(), 2);
+
+        // Once the last slice containing 10.0.0.2 drops it, it is removed
+        let actions = process_event(
+            &Event::Delete(b),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        assert_eq!(actions, vec![remove("10.0.0.2:50051")]);
+        assert_eq!(tracker.len(), 1);
+    }
+
+    #[test]
+    fn process_event_reapplying_same_slice_does_not_inflate_counts() {
+        let mut tracker = EndpointTracker::<SocketAddr>::default();
+        let a = make_slice("svc-a", vec![make_endpoint(vec!["10.0.0.1"], Some(true))]);
+
+        process_event(
+            &Event::Apply(a.clone()),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        let actions = process_event(
+            &Event::Apply(a.clone()),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        assert!(actions.is_empty());
+
+        let actions = process_event(
+            &Event::Delete(a),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        assert_eq!(actions, vec![remove("10.0.0.1:50051")]);
+        assert_eq!(tracker.len(), 0);
+    }
+
+    #[test]
+    fn process_event_delete_removes_known_endpoints() {
+        let slice = make_slice(
+            "svc-a",
+            vec![make_endpoint(vec!["10.0.0.1", "10.0.0.2"], Some(true))],
+        );
+
+        let mut tracker = EndpointTracker::<SocketAddr>::default();
+        process_event(
+            &Event::Apply(slice.clone()),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+
+        let actions = process_event(
+            &Event::Delete(slice),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+
+        assert_eq!(actions.len(), 2);
+        assert!(actions.contains(&remove("10.0.0.1:50051")));
+        assert!(actions.contains(&remove("10.0.0.2:50051")));
+        assert_eq!(tracker.len(), 0);
+    }
+
+    // Port renumbering tests
+
+    fn make_slice_with_port(name: &str, addresses: Vec<&str>, port: i32) -> EndpointSlice {
+        EndpointSlice {
+            ports: Some(vec![make_port(Some("grpc"), port)]),
+            ..make_slice(name, vec![make_endpoint(addresses, Some(true))])
+        }
+    }
+
+    #[test]
+    fn process_event_named_port_renumbering_replaces_endpoints() {
+        let mut tracker = EndpointTracker::<SocketAddr>::default();
+        let port = Port::Name("grpc".to_string());
+
+        let slice = make_slice_with_port("svc-a", vec!["10.0.0.1"], 8080);
+        process_event(
+            &Event::Apply(slice),
+            &mut tracker,
+            &port,
+            ReadinessPolicy::Ready,
+        );
+
+        let slice = make_slice_with_port("svc-a", vec!["10.0.0.1"], 9090);
+        let actions = process_event(
+            &Event::Apply(slice),
+            &mut tracker,
+            &port,
+            ReadinessPolicy::Ready,
+        );
+
+        assert_eq!(
+            render(&actions),
+            vec!["remove 10.0.0.1:8080", "insert 10.0.0.1:9090"]
+        );
+        assert_eq!(tracker.len(), 1);
+    }
+
+    #[test]
+    fn process_event_named_port_removed_from_slice_removes_endpoints() {
+        let mut tracker = EndpointTracker::<SocketAddr>::default();
+        let port = Port::Name("grpc".to_string());
+
+        let slice = make_slice_with_port("svc-a", vec!["10.0.0.1"], 8080);
+        process_event(
+            &Event::Apply(slice),
+            &mut tracker,
+            &port,
+            ReadinessPolicy::Ready,
+        );
+
+        let slice = make_slice("svc-a", vec![make_endpoint(vec!["10.0.0.1"], Some(true))]);
+        let actions = process_event(
+            &Event::Apply(slice),
+            &mut tracker,
+            &port,
+            ReadinessPolicy::Ready,
+        );
+
+        assert_eq!(actions, vec![remove("10.0.0.1:8080")]);
+        assert_eq!(tracker.slices_with_port(), 0);
+    }
+
+    #[test]
+    fn process_event_delete_after_renumbering_uses_tracked_port() {
+        let mut tracker = EndpointTracker::<SocketAddr>::default();
+        let port = Port::Name("grpc".to_string());
+
+        let slice = make_slice_with_port("svc-a", vec!["10.0.0.1"], 8080);
+        process_event(
+            &Event::Apply(slice),
+            &mut tracker,
+            &port,
+            ReadinessPolicy::Ready,
+        );
+
+        let slice = make_slice_with_port("svc-a", vec!["10.0.0.1"], 9090);
+        process_event(
+            &Event::Apply(slice),
+            &mut tracker,
+            &port,
+            ReadinessPolicy::Ready,
+        );
+
+        // The deleted object carries yet another port number
+        let slice = make_slice_with_port("svc-a", vec!["10.0.0.1"], 7070);
+        let actions = process_event(
+            &Event::Delete(slice),
+            &mut tracker,
+            &port,
+            ReadinessPolicy::Ready,
+        );
+
+        assert_eq!(actions, vec![remove("10.0.0.1:9090")]);
+        assert_eq!(tracker.len(), 0);
+    }
+
+    #[test]
+    fn process_event_slices_may_resolve_different_port_numbers() {
+        // During a rollout, old and new pods may expose the named port on different numbers
+        let mut tracker = EndpointTracker::<SocketAddr>::default();
+        let port = Port::Name("grpc".to_string());
+
+        let old = make_slice_with_port("svc-old", vec!["10.0.0.1"], 8080);
+        let new = make_slice_with_port("svc-new", vec!["10.0.0.2"], 9090);
+        process_event(
+            &Event::Apply(old),
+            &mut tracker,
+            &port,
+            ReadinessPolicy::Ready,
+        );
+        let actions = process_event(
+            &Event::Apply(new),
+            &mut tracker,
+            &port,
+            ReadinessPolicy::Ready,
+        );
+
+        assert_eq!(render(&actions), vec!["insert 10.0.0.2:9090"]);
+        assert_eq!(tracker.len(), 2);
+    }
+
+    #[test]
+    fn process_event_delete_uses_last_known_state() {
+        let mut tracker = EndpointTracker::<SocketAddr>::default();
+        let slice = make_slice("svc-a", vec![make_endpoint(vec!["10.0.0.1"], Some(true))]);
+        process_event(
+            &Event::Apply(slice),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+
+        // The deleted object may carry a different endpoint list than what was tracked
+        let slice = make_slice("svc-a", vec![make_endpoint(vec!["10.0.0.2"], Some(true))]);
+        let actions = process_event(
+            &Event::Delete(slice),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+
+        assert_eq!(actions, vec![remove("10.0.0.1:50051")]);
+        assert_eq!(tracker.len(), 0);
+    }
+
+    #[test]
+    fn process_event_delete_unknown_slice_returns_empty() {
+        let slice = make_slice("svc-a", vec![make_endpoint(vec!["10.0.0.1"], Some(true))]);
+
+        let mut tracker = EndpointTracker::<SocketAddr>::default();
+        let actions = process_event(
+            &Event::Delete(slice),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+
+        assert!(actions.is_empty());
+    }
+
+    #[test]
+    fn process_event_init_returns_empty() {
+        let mut tracker = EndpointTracker::<SocketAddr>::default();
+        let actions = process_event(
+            &Event::Init,
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+
+        assert!(actions.is_empty());
+    }
+
+    #[test]
+    fn process_event_init_done_returns_empty() {
+        let mut tracker = EndpointTracker::<SocketAddr>::default();
+        let actions = process_event(
+            &Event::InitDone,
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+
+        assert!(actions.is_empty());
     }
 
-    #[test]
-    fn port_from_string() {
-        let port: Port = String::from("grpc").into();
-        assert_eq!(port, Port::Name("grpc".to_string()));
+    #[test]
+    fn process_event_init_buffers_until_init_done() {
+        let mut tracker = EndpointTracker::<SocketAddr>::default();
+        let slice = make_slice("svc-a", vec![make_endpoint(vec!["10.0.0.1"], Some(true))]);
+
+        assert!(
+            process_event(
+                &Event::Init,
+                &mut tracker,
+                &Port::Number(50051),
+                ReadinessPolicy::Ready
+            )
+            .is_empty()
+        );
+
+        let actions = process_event(
+            &Event::InitApply(slice),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        assert!(actions.is_empty());
+        assert_eq!(tracker.len(), 0);
+
+        let actions = process_event(
+            &Event::InitDone,
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        assert_eq!(actions, vec![insert("10.0.0.1:50051")]);
+        assert_eq!(tracker.len(), 1);
+    }
+
+    #[test]
+    fn process_event_relist_removes_vanished_endpoints() {
+        let mut tracker = EndpointTracker::<SocketAddr>::default();
+        let a = make_slice(
+            "svc-a",
+            vec![make_endpoint(vec!["10.0.0.1", "10.0.0.2"], Some(true))],
+        );
+
+        let b = make_slice("svc-b", vec![make_endpoint(vec!["10.0.0.3"], Some(true))]);
+
+        process_event(
+            &Event::Apply(a),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        process_event(
+            &Event::Apply(b),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+
+        // While the watch was down, 10.0.0.2 went away, slice b was deleted and 10.0.0.4 appeared
+        let a = make_slice(
+            "svc-a",
+            vec![make_endpoint(vec!["10.0.0.1", "10.0.0.4"], Some(true))],
+        );
+
+        process_event(
+            &Event::Init,
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        process_event(
+            &Event::InitApply(a),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        let actions = process_event(
+            &Event::InitDone,
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+
+        assert_eq!(actions.len(), 3);
+        assert!(actions.contains(&remove("10.0.0.2:50051")));
+        assert!(actions.contains(&remove("10.0.0.3:50051")));
+        assert!(actions.contains(&insert("10.0.0.4:50051")));
+        assert_eq!(tracker.len(), 2);
+    }
+
+    #[test]
+    fn process_event_relist_keeps_surviving_endpoints() {
+        let mut tracker = EndpointTracker::<SocketAddr>::default();
+        let a = make_slice("svc-a", vec![make_endpoint(vec!["10.0.0.1"], Some(true))]);
+
+        process_event(
+            &Event::Apply(a),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+
+        // The address moved to a differently named slice; no churn is expected
+        let b = make_slice("svc-b", vec![make_endpoint(vec!["10.0.0.1"], Some(true))]);
+
+        process_event(
+            &Event::Init,
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        process_event(
+            &Event::InitApply(b.clone()),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        let actions = process_event(
+            &Event::InitDone,
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        assert!(actions.is_empty());
+
+        // The reconciled state is used for subsequent events
+        let actions = process_event(
+            &Event::Delete(b),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        assert_eq!(actions, vec![remove("10.0.0.1:50051")]);
     }
 
-    // DiscoveryConfig tests
-
     #[test]
-    fn config_new_with_numeric_port() {
-        let config = DiscoveryConfig::new("my-service", 50051_u16);
+    fn process_event_relist_with_no_slices_removes_everything() {
+        let mut tracker = EndpointTracker::<SocketAddr>::default();
+        let a = make_slice(
+            "svc-a",
+            vec![make_endpoint(vec!["10.0.0.1", "10.0.0.2"], Some(true))],
+        );
 
-        assert_eq!(config.service_name, "my-service");
-        assert!(config.namespace.is_none());
-        assert_eq!(config.port, Port::Number(50051));
+        process_event(
+            &Event::Apply(a),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        process_event(
+            &Event::Init,
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        let actions = process_event(
+            &Event::InitDone,
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+
+        assert_eq!(actions.len(), 2);
+        assert!(actions.contains(&remove("10.0.0.1:50051")));
+        assert!(actions.contains(&remove("10.0.0.2:50051")));
+        assert_eq!(tracker.len(), 0);
     }
 
     #[test]
-    fn config_new_with_named_port() {
-        let config = DiscoveryConfig::new("my-service", "grpc");
+    fn process_event_relist_counts_shared_addresses() {
+        let mut tracker = EndpointTracker::<SocketAddr>::default();
+        let a = make_slice("svc-a", vec![make_endpoint(vec!["10.0.0.1"], Some(true))]);
+        let b = make_slice("svc-b", vec![make_endpoint(vec!["10.0.0.1"], Some(true))]);
+
+        process_event(
+            &Event::Init,
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        process_event(
+            &Event::InitApply(a.clone()),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        process_event(
+            &Event::InitApply(b),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        process_event(
+            &Event::InitDone,
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
 
-        assert_eq!(config.service_name, "my-service");
-        assert!(config.namespace.is_none());
-        assert_eq!(config.port, Port::Name("grpc".to_string()));
+        let actions = process_event(
+            &Event::Delete(a),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
+        assert!(actions.is_empty());
+        assert_eq!(tracker.len(), 1);
     }
 
     #[test]
-    fn config_with_namespace() {
-        let config = DiscoveryConfig::new("my-service", 50051_u16).namespace("my-namespace");
+    fn tracker_counts_slices_with_port() {
+        let mut tracker = EndpointTracker::<SocketAddr>::default();
+        let port = Port::Name("grpc".to_string());
 
-        assert_eq!(config.service_name, "my-service");
-        assert_eq!(config.namespace, Some("my-namespace".to_string()));
-        assert_eq!(config.port, Port::Number(50051));
-    }
+        let mut a = make_slice("svc-a", vec![make_endpoint(vec!["10.0.0.1"], Some(true))]);
+        a.ports = Some(vec![make_port(Some("grpc"), 9090)]);
 
-    // Helper to create an endpoint with addresses and optional ready condition
-    fn make_endpoint(addresses: Vec<&str>, ready: Option<bool>) -> Endpoint {
-        Endpoint {
-            addresses: addresses.into_iter().map(String::from).collect(),
-            conditions: Some(EndpointConditions {
-                ready,
-                ..Default::default()
-            }),
-            ..Default::default()
-        }
-    }
+        let mut b = make_slice("svc-b", vec![make_endpoint(vec!["10.0.0.2"], Some(true))]);
+        b.ports = Some(vec![make_port(Some("http"), 8080)]);
 
-    // Helper to create an endpoint port
-    fn make_port(name: Option<&str>, port: i32) -> EndpointPort {
-        EndpointPort {
-            name: name.map(String::from),
-            port: Some(port),
-            ..Default::default()
-        }
+        process_event(
+            &Event::Apply(a),
+            &mut tracker,
+            &port,
+            ReadinessPolicy::Ready,
+        );
+        process_event(
+            &Event::Apply(b),
+            &mut tracker,
+            &port,
+            ReadinessPolicy::Ready,
+        );
+
+        assert_eq!(tracker.slice_count(), 2);
+        assert_eq!(tracker.slices_with_port(), 1);
+        assert_eq!(tracker.len(), 1);
     }
 
-    // extract_ready_endpoints tests
+    // Published endpoint count tests
 
-    #[test]
-    fn extract_ready_endpoints_empty_slice() {
-        let slice = EndpointSlice {
-            endpoints: Vec::new(),
-            ..Default::default()
-        };
+    #[tokio::test]
+    async fn published_count_with_failed_build_held_removal_and_pending_batch() {
+        // Endpoints on port 1 fail to build
+        let (tx, _rx) = tokio::sync::mpsc::channel(16);
+        let mut publisher = Publisher::new(tx, |endpoint: DiscoveredEndpoint| async move {
+            if endpoint.address.port() == 1 {
+                return Err("credentials unavailable");
+            }
 
-        let addrs = extract_ready_endpoints(&slice, &Port::Number(50051));
-        assert!(addrs.is_empty());
+            Ok(
+                tonic::transport::Endpoint::from_shared(format!("http://{}", endpoint.address))
+                    .unwrap(),
+            )
+        });
+
+        let mut batch = Batch::<SocketAddr>::new(Some(Duration::from_secs(1)));
+        let mut guard = PanicGuard::new(Some(PanicThreshold::default().min_endpoints(2)));
+        let now = Instant::now();
+
+        batch.add(vec![
+            insert("10.0.0.1:50051"),
+            insert("10.0.0.2:50051"),
+            insert("10.0.0.3:1"),
+        ]);
+        publisher
+            .publish(guard.apply(batch.flush(), now))
+            .await
+            .unwrap();
+        assert_eq!(publisher.published(), 2);
+
+        // One removal is held back to keep two endpoints
+        batch.add(vec![
+            EndpointAction::Remove("10.0.0.1:50051".parse().unwrap()),
+            EndpointAction::Remove("10.0.0.2:50051".parse().unwrap()),
+        ]);
+        publisher
+            .publish(guard.apply(batch.flush(), now))
+            .await
+            .unwrap();
+        assert_eq!(publisher.published(), 1);
+
+        // Changes collected in the batch are not published yet
+        batch.add(vec![
+            insert("10.0.0.4:50051"),
+            EndpointAction::Remove("10.0.0.3:1".parse().unwrap()),
+        ]);
+        publisher
+            .publish(guard.apply(Vec::new(), now))
+            .await
+            .unwrap();
+        assert_eq!(publisher.published(), 1);
+
+        // The new endpoint makes room for the held back removal
+        publisher
+            .publish(guard.apply(batch.flush(), now))
+            .await
+            .unwrap();
+        assert_eq!(publisher.published(), 1);
     }
 
-    #[test]
-    fn extract_ready_endpoints_with_numeric_port() {
-        let slice = EndpointSlice {
-            endpoints: vec![make_endpoint(vec!["10.0.0.1", "10.0.0.2"], Some(true))],
-            ..Default::default()
-        };
+    // Discovery loop tests
 
-        let addrs = extract_ready_endpoints(&slice, &Port::Number(50051));
+    // API server listing one EndpointSlice, then answering watches in order with
+    // the given events after the given delay; later watches never answer
+    #[derive(Clone)]
+    struct Scripted {
+        list: String,
+        watches: Arc<Mutex<VecDeque<(Duration, String)>>>,
+    }
 
-        assert_eq!(addrs.len(), 2);
-        assert!(addrs.contains(&"10.0.0.1:50051".parse().unwrap()));
-        assert!(addrs.contains(&"10.0.0.2:50051".parse().unwrap()));
+    impl Scripted {
+        fn new(addresses: &[&str], watches: Vec<(Duration, &[&str])>) -> Self {
+            let list = format!(
+                r#"{{"apiVersion":"discovery.k8s.io/v1","kind":"EndpointSliceList","metadata":{{"resourceVersion":"1"}},"items":[{}]}}"#,
+                scripted_slice(addresses)
+            );
+            let watches = watches
+                .into_iter()
+                .map(|(delay, addresses)| {
+                    let event = format!(
+                        r#"{{"type":"MODIFIED","object":{}}}"#,
+                        scripted_slice(addresses)
+                    );
+                    (delay, event + "\n")
+                })
+                .collect();
+
+            Self {
+                list,
+                watches: Arc::new(Mutex::new(watches)),
+            }
+        }
     }
 
-    #[test]
-    fn extract_ready_endpoints_with_named_port() {
-        let slice = EndpointSlice {
-            endpoints: vec![make_endpoint(vec!["10.0.0.1"], Some(true))],
-            ports: Some(vec![make_port(Some("grpc"), 9090)]),
-            ..Default::default()
-        };
+    fn scripted_slice(addresses: &[&str]) -> String {
+        let endpoints: Vec<_> = addresses
+            .iter()
+            .map(|address| {
+                format!(r#"{{"addresses":["{address}"],"conditions":{{"ready":true}}}}"#)
+            })
+            .collect();
+
+        format!(
+            r#"{{"apiVersion":"discovery.k8s.io/v1","kind":"EndpointSlice","metadata":{{"name":"my-service-abc","namespace":"default","resourceVersion":"1","labels":{{"kubernetes.io/service-name":"my-service"}}}},"addressType":"IPv4","ports":[{{"port":50051}}],"endpoints":[{}]}}"#,
+            endpoints.join(",")
+        )
+    }
 
-        let addrs = extract_ready_endpoints(&slice, &Port::Name("grpc".to_string()));
+    impl tower::Service<http::Request<kube::client::Body>> for Scripted {
+        type Response = http::Response<kube::client::Body>;
+        type Error = Infallible;
+        type Future = BoxFuture<'static, std::result::Result<Self::Response, Infallible>>;
 
-        assert_eq!(addrs.len(), 1);
-        assert!(addrs.contains(&"10.0.0.1:9090".parse().unwrap()));
-    }
+        fn poll_ready(
+            &mut self,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::result::Result<(), Infallible>> {
+            std::task::Poll::Ready(Ok(()))
+        }
 
-    #[test]
-    fn extract_ready_endpoints_named_port_not_found() {
-        let slice = EndpointSlice {
-            endpoints: vec![make_endpoint(vec!["10.0.0.1"], Some(true))],
-            ports: Some(vec![make_port(Some("http"), 8080)]),
-            ..Default::default()
-        };
+        fn call(&mut self, request: http::Request<kube::client::Body>) -> Self::Future {
+            let watch = request
+                .uri()
+                .query()
+                .is_some_and(|query| query.contains("watch=true"));
+            let answer = if watch {
+                self.watches.lock().unwrap().pop_front()
+            } else {
+                Some((Duration::ZERO, self.list.clone()))
+            };
 
-        let addrs = extract_ready_endpoints(&slice, &Port::Name("grpc".to_string()));
-        assert!(addrs.is_empty());
-    }
+            Box::pin(async move {
+                let Some((delay, body)) = answer else {
+                    return future::pending().await;
+                };
 
-    #[test]
-    fn extract_ready_endpoints_named_port_no_ports_defined() {
-        let slice = EndpointSlice {
-            endpoints: vec![make_endpoint(vec!["10.0.0.1"], Some(true))],
-            ports: None,
-            ..Default::default()
-        };
+                tokio::time::sleep(delay).await;
+                let response = http::Response::builder()
+                    .status(200)
+                    .header("content-type", "application/json")
+                    .body(kube::client::Body::from(body.into_bytes()))
+                    .unwrap();
 
-        let addrs = extract_ready_endpoints(&slice, &Port::Name("grpc".to_string()));
-        assert!(addrs.is_empty());
+                Ok(response)
+            })
+        }
     }
 
-    #[test]
-    fn extract_ready_endpoints_skips_not_ready() {
-        let slice = EndpointSlice {
-            endpoints: vec![
-                make_endpoint(vec!["10.0.0.1"], Some(true)),
-                make_endpoint(vec!["10.0.0.2"], Some(false)),
-            ],
-            ..Default::default()
-        };
-
-        let addrs = extract_ready_endpoints(&slice, &Port::Number(50051));
+    // Starts discovery against the scripted API server with a settle window of
+    // one second and a panic threshold keeping two endpoints for ten seconds
+    fn discover_scripted(
+        api: Scripted,
+    ) -> (
+        DiscoveryHandle,
+        tokio::sync::mpsc::Receiver<Change<SocketAddr, tonic::transport::Endpoint>>,
+    ) {
+        let threshold = PanicThreshold::default()
+            .min_endpoints(2)
+            .confirmation(Duration::from_secs(10));
+        let config = DiscoveryConfig::new("my-service", 50051_u16)
+            .client(Client::new(api, "default"))
+            .settle_window(Duration::from_secs(1))
+            .panic_threshold(threshold);
+
+        let (tx, rx) = tokio::sync::mpsc::channel(16);
+        let handle = discover(config, tx, |endpoint| {
+            tonic::transport::Endpoint::from_shared(format!("http://{}", endpoint.address)).unwrap()
+        });
+
+        (handle, rx)
+    }
 
-        assert_eq!(addrs.len(), 1);
-        assert!(addrs.contains(&"10.0.0.1:50051".parse().unwrap()));
+    // Receives the next change with the whole seconds passed since `start`
+    async fn next_change(
+        rx: &mut tokio::sync::mpsc::Receiver<Change<SocketAddr, tonic::transport::Endpoint>>,
+        start: Instant,
+    ) -> (u64, &'static str, SocketAddr) {
+        let change = rx.recv().await.expect("discovery stopped");
+        let secs = start.elapsed().as_secs();
+        match change {
+            Change::Insert(key, _) => (secs, "insert", key),
+            Change::Remove(key) => (secs, "remove", key),
+        }
     }
 
-    #[test]
-    fn extract_ready_endpoints_ready_defaults_to_true() {
-        // When ready is None, it should default to true
-        let slice = EndpointSlice {
-            endpoints: vec![Endpoint {
-                addresses: vec!["10.0.0.1".to_string()],
-                conditions: Some(EndpointConditions {
-                    ready: None,
-                    ..Default::default()
-                }),
-                ..Default::default()
-            }],
-            ..Default::default()
-        };
+    #[tokio::test(start_paused = true)]
+    async fn discovery_holds_back_settled_mass_removal_until_confirmed() {
+        let all: &[&str] = &["10.0.0.1", "10.0.0.2", "10.0.0.3"];
+        let api = Scripted::new(all, vec![(Duration::from_secs(5), &[])]);
+        let start = Instant::now();
+        let (_handle, mut rx) = discover_scripted(api);
+
+        // The initial endpoints are published once the settle window elapsed
+        for _ in 0..3 {
+            assert!(matches!(
+                next_change(&mut rx, start).await,
+                (1, "insert", _)
+            ));
+        }
 
-        let addrs = extract_ready_endpoints(&slice, &Port::Number(50051));
+        // The removals settle together, and only the one not crossing the
+        // threshold is let through
+        let mut removed = HashSet::new();
+        let (secs, kind, key) = next_change(&mut rx, start).await;
+        assert_eq!((secs, kind), (6, "remove"));
+        removed.insert(key);
+
+        // The others proceed once confirmed
+        for _ in 0..2 {
+            let (secs, kind, key) = next_change(&mut rx, start).await;
+            assert_eq!((secs, kind), (16, "remove"));
+            removed.insert(key);
+        }
 
-        assert_eq!(addrs.len(), 1);
-        assert!(addrs.contains(&"10.0.0.1:50051".parse().unwrap()));
+        assert_eq!(removed.len(), 3);
     }
 
-    #[test]
-    fn extract_ready_endpoints_no_conditions_defaults_to_ready() {
-        // When conditions is None entirely, should default to ready
-        let slice = EndpointSlice {
-            endpoints: vec![Endpoint {
-                addresses: vec!["10.0.0.1".to_string()],
-                conditions: None,
-                ..Default::default()
-            }],
-            ..Default::default()
-        };
+    #[tokio::test(start_paused = true)]
+    async fn discovery_keeps_held_back_endpoints_returning_before_confirmation() {
+        let all: &[&str] = &["10.0.0.1", "10.0.0.2", "10.0.0.3"];
+        let api = Scripted::new(
+            all,
+            vec![(Duration::from_secs(5), &[]), (Duration::from_secs(3), all)],
+        );
+        let start = Instant::now();
+        let (_handle, mut rx) = discover_scripted(api);
+
+        for _ in 0..3 {
+            assert!(matches!(
+                next_change(&mut rx, start).await,
+                (1, "insert", _)
+            ));
+        }
 
-        let addrs = extract_ready_endpoints(&slice, &Port::Number(50051));
+        let (secs, kind, removed) = next_change(&mut rx, start).await;
+        assert_eq!((secs, kind), (6, "remove"));
 
-        assert_eq!(addrs.len(), 1);
-        assert!(addrs.contains(&"10.0.0.1:50051".parse().unwrap()));
+        // Only the endpoint let through comes back once the settle window elapsed,
+        // the held back ones were never removed from the channel
+        assert_eq!(next_change(&mut rx, start).await, (9, "insert", removed));
+        let later = tokio::time::timeout(Duration::from_secs(30), rx.recv()).await;
+        assert!(later.is_err(), "unexpected change: {later:?}");
     }
 
-    #[test]
-    fn extract_ready_endpoints_skips_invalid_ip() {
-        let slice = EndpointSlice {
-            endpoints: vec![Endpoint {
-                addresses: vec!["not-an-ip".to_string(), "10.0.0.1".to_string()],
-                conditions: Some(EndpointConditions {
-                    ready: Some(true),
-                    ..Default::default()
-                }),
-                ..Default::default()
-            }],
-            ..Default::default()
-        };
+    // Watch retry tests
 
-        let addrs = extract_ready_endpoints(&slice, &Port::Number(50051));
+    fn failing_watch(
+        results: Vec<watcher::Result<u32>>,
+    ) -> BoxStream<'static, watcher::Result<u32>> {
+        stream::iter(results).boxed()
+    }
 
-        assert_eq!(addrs.len(), 1);
-        assert!(addrs.contains(&"10.0.0.1:50051".parse().unwrap()));
+    fn outcomes(items: Vec<Watched<u32>>) -> Vec<String> {
+        items
+            .into_iter()
+            .map(|item| match item {
+                Watched::Event(event) => format!("event {event}"),
+                Watched::Failed(_, Some(delay)) => format!("retry in {delay:?}"),
+                Watched::Failed(_, None) => "give up".to_string(),
+            })
+            .collect()
     }
 
-    #[test]
-    fn extract_ready_endpoints_ipv6() {
-        let slice = EndpointSlice {
-            endpoints: vec![make_endpoint(vec!["::1", "2001:db8::1"], Some(true))],
-            ..Default::default()
-        };
+    #[tokio::test]
+    async fn retrying_counts_consecutive_failures_of_the_watch() {
+        let policy = RetryPolicy::default()
+            .initial_backoff(Duration::from_millis(1))
+            .max_failures(3);
+
+        let watch = failing_watch(vec![
+            Err(watcher::Error::NoResourceVersion),
+            Err(watcher::Error::NoResourceVersion),
+            Ok(1),
+            Err(watcher::Error::NoResourceVersion),
+        ]);
+
+        let items = retrying(watch, policy).collect::<Vec<_>>().await;
+        assert_eq!(
+            outcomes(items),
+            vec!["retry in 1ms", "retry in 2ms", "event 1", "retry in 1ms"]
+        );
+    }
 
-        let addrs = extract_ready_endpoints(&slice, &Port::Number(50051));
+    #[tokio::test]
+    async fn retrying_gives_up_per_policy() {
+        let policy = RetryPolicy::default()
+            .initial_backoff(Duration::from_millis(1))
+            .max_failures(2);
 
-        assert_eq!(addrs.len(), 2);
-        assert!(addrs.contains(&"[::1]:50051".parse().unwrap()));
-        assert!(addrs.contains(&"[2001:db8::1]:50051".parse().unwrap()));
-    }
+        let watch = failing_watch(vec![
+            Err(watcher::Error::NoResourceVersion),
+            Err(watcher::Error::NoResourceVersion),
+        ]);
 
-    #[test]
-    fn extract_ready_endpoints_multiple_endpoints() {
-        let slice = EndpointSlice {
-            endpoints: vec![
-                make_endpoint(vec!["10.0.0.1"], Some(true)),
-                make_endpoint(vec!["10.0.0.2"], Some(true)),
-                make_endpoint(vec!["10.0.0.3"], Some(true)),
-            ],
-            ..Default::default()
-        };
+        let items = retrying(watch, policy).collect::<Vec<_>>().await;
+        assert_eq!(outcomes(items), vec!["retry in 1ms", "give up"]);
+    }
 
-        let addrs = extract_ready_endpoints(&slice, &Port::Number(50051));
+    #[tokio::test]
+    async fn retrying_watch_does_not_hold_up_other_watches() {
+        let policy = RetryPolicy::default().initial_backoff(Duration::from_secs(1000));
+        let failing = retrying(
+            stream::iter(vec![Err(watcher::Error::NoResourceVersion), Ok(1)])
+                .chain(stream::pending())
+                .boxed(),
+            policy.clone(),
+        );
+        let healthy = retrying(
+            stream::iter(vec![Ok(2), Ok(3)])
+                .chain(stream::pending())
+                .boxed(),
+            policy,
+        );
 
-        assert_eq!(addrs.len(), 3);
-        assert!(addrs.contains(&"10.0.0.1:50051".parse().unwrap()));
-        assert!(addrs.contains(&"10.0.0.2:50051".parse().unwrap()));
-        assert!(addrs.contains(&"10.0.0.3:50051".parse().unwrap()));
+        // The failing watch backs off for a long time while the other one proceeds
+        let events = stream::select_all([failing, healthy])
+            .filter_map(|item| {
+                future::ready(match item {
+                    Watched::Event(event) => Some(event),
+                    Watched::Failed(..) => None,
+                })
+            })
+            .take(2)
+            .collect::<Vec<_>>();
+
+        let events = tokio::time::timeout(Duration::from_secs(5), events)
+            .await
+            .unwrap();
+        assert_eq!(events, vec![2, 3]);
     }
 
-    #[test]
-    fn extract_ready_endpoints_deduplicates_addresses() {
-        // Same address in multiple endpoints should only appear once
-        let slice = EndpointSlice {
-            endpoints: vec![
-                make_endpoint(vec!["10.0.0.1"], Some(true)),
-                make_endpoint(vec!["10.0.0.1"], Some(true)),
-            ],
-            ..Default::default()
-        };
+    // Weight tests
 
-        let addrs = extract_ready_endpoints(&slice, &Port::Number(50051));
+    fn weighted_pod_slice() -> EndpointSlice {
+        let mut endpoint = make_endpoint(vec!["10.0.0.1"], Some(true));
+        endpoint.target_ref = Some(ObjectReference {
+            kind: Some("Pod".to_string()),
+            namespace: Some("default".to_string()),
+            name: Some("pod-1".to_string()),
+            ..Default::default()
+        });
 
-        assert_eq!(addrs.len(), 1);
-        assert!(addrs.contains(&"10.0.0.1:50051".parse().unwrap()));
+        make_slice("svc-a", vec![endpoint])
     }
 
-    #[test]
-    fn extract_ready_endpoints_multiple_ports_finds_correct_one() {
-        let slice = EndpointSlice {
-            endpoints: vec![make_endpoint(vec!["10.0.0.1"], Some(true))],
-            ports: Some(vec![
-                make_port(Some("http"), 8080),
-                make_port(Some("grpc"), 9090),
-                make_port(Some("metrics"), 9100),
-            ]),
+    fn weighted_pod(weight: &str) -> Event<PartialObjectMeta<Pod>> {
+        Event::Apply(PartialObjectMeta {
+            metadata: ObjectMeta {
+                namespace: Some("default".to_string()),
+                name: Some("pod-1".to_string()),
+                annotations: Some([("weight".to_string(), weight.to_string())].into()),
+                ..Default::default()
+            },
             ..Default::default()
-        };
+        })
+    }
 
-        let addrs = extract_ready_endpoints(&slice, &Port::Name("grpc".to_string()));
+    fn annotation() -> WeightSource {
+        WeightSource::Annotation {
+            key: "weight".to_string(),
+            selector: "app=my-app".to_string(),
+        }
+    }
 
-        assert_eq!(addrs.len(), 1);
-        assert!(addrs.contains(&"10.0.0.1:9090".parse().unwrap()));
+    fn weights() -> SharedWeights {
+        SharedWeights::new(Mutex::new(Weights::new(Some(annotation()))))
     }
 
-    // process_event tests
+    // Applies a pod event to the shared weights, returning whether a weight changed
+    fn set_weight(weights: &SharedWeights, weight: &str) -> bool {
+        weights.lock().unwrap().apply(&weighted_pod(weight))
+    }
 
     #[test]
-    fn process_event_apply_inserts_new_endpoints() {
-        let slice = EndpointSlice {
-            endpoints: vec![make_endpoint(vec!["10.0.0.1", "10.0.0.2"], Some(true))],
-            ..Default::default()
-        };
+    fn process_event_weighs_endpoints_by_pod() {
+        let weights = weights();
+        let mut tracker = EndpointTracker::<Weighted<SocketAddr>>::weighted(weights.clone());
+        set_weight(&weights, "3");
+
+        let actions = process_event(
+            &Event::Apply(weighted_pod_slice()),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
 
-        let mut known = HashSet::new();
-        let actions = process_event(&Event::Apply(slice), &mut known, &Port::Number(50051));
+        let [EndpointAction::Insert(key, endpoint)] = actions.as_slice() else {
+            panic!("expected an insert, got {actions:?}");
+        };
 
-        assert_eq!(actions.len(), 2);
-        assert!(actions.contains(&EndpointAction::Insert("10.0.0.1:50051".parse().unwrap())));
-        assert!(actions.contains(&EndpointAction::Insert("10.0.0.2:50051".parse().unwrap())));
-        assert_eq!(known.len(), 2);
+        assert_eq!(key.weight, 3);
+        assert_eq!(endpoint.weight, 3);
     }
 
     #[test]
-    fn process_event_apply_skips_known_endpoints() {
-        let slice = EndpointSlice {
-            endpoints: vec![make_endpoint(vec!["10.0.0.1", "10.0.0.2"], Some(true))],
-            ..Default::default()
-        };
+    fn reweigh_replaces_weighted_keys() {
+        let weights = weights();
+        let mut tracker = EndpointTracker::<Weighted<SocketAddr>>::weighted(weights.clone());
+        process_event(
+            &Event::Apply(weighted_pod_slice()),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
 
-        let mut known = HashSet::new();
-        known.insert("10.0.0.1:50051".parse().unwrap());
+        assert!(set_weight(&weights, "5"));
+        let actions = tracker.reweigh();
+        let [
+            EndpointAction::Remove(removed),
+            EndpointAction::Insert(inserted, _),
+        ] = actions.as_slice()
+        else {
+            panic!("expected a remove followed by an insert, got {actions:?}");
+        };
 
-        let actions = process_event(&Event::Apply(slice), &mut known, &Port::Number(50051));
+        assert_eq!((removed.weight, inserted.weight), (1, 5));
+        assert_eq!(tracker.len(), 1);
 
-        // Only 10.0.0.2 should be inserted since 10.0.0.1 is already known
-        assert_eq!(actions.len(), 1);
-        assert!(actions.contains(&EndpointAction::Insert("10.0.0.2:50051".parse().unwrap())));
-        assert_eq!(known.len(), 2);
+        // Pod updates leaving the weight unchanged are ignored
+        assert!(!set_weight(&weights, "5"));
+        assert!(tracker.reweigh().is_empty());
     }
 
     #[test]
-    fn process_event_init_apply_inserts_endpoints() {
-        let slice = EndpointSlice {
-            endpoints: vec![make_endpoint(vec!["10.0.0.1"], Some(true))],
-            ..Default::default()
-        };
+    fn reweigh_updates_unweighted_keys() {
+        let weights = weights();
+        let mut tracker = EndpointTracker::<SocketAddr>::weighted(weights.clone());
+        process_event(
+            &Event::Apply(weighted_pod_slice()),
+            &mut tracker,
+            &Port::Number(50051),
+            ReadinessPolicy::Ready,
+        );
 
-        let mut known = HashSet::new();
-        let actions = process_event(&Event::InitApply(slice), &mut known, &Port::Number(50051));
+        set_weight(&weights, "5");
+        let actions = tracker.reweigh();
+        let [EndpointAction::Update(_, endpoint)] = actions.as_slice() else {
+            panic!("expected an update, got {actions:?}");
+        };
 
-        assert_eq!(actions.len(), 1);
-        assert!(actions.contains(&EndpointAction::Insert("10.0.0.1:50051".parse().unwrap())));
+        assert_eq!(endpoint.weight, 5);
     }
 
-    #[test]
-    fn process_event_delete_removes_known_endpoints() {
-        let slice = EndpointSlice {
-            endpoints: vec![make_endpoint(vec!["10.0.0.1", "10.0.0.2"], Some(true))],
-            ..Default::default()
-        };
+    async fn sources(services: Vec<ServiceRef>) -> Vec<Source<SocketAddr>> {
+        let mut sources = Vec::new();
+        for service in services {
+            sources.push(Source::new(&test_client(), None, service).await.unwrap());
+        }
 
-        let mut known = HashSet::new();
-        known.insert("10.0.0.1:50051".parse().unwrap());
-        known.insert("10.0.0.2:50051".parse().unwrap());
+        sources
+    }
 
-        let actions = process_event(&Event::Delete(slice), &mut known, &Port::Number(50051));
+    fn owners(
+        watches: &[(Vec<usize>, BoxStream<'static, watcher::Result<WatchEvent>>)],
+    ) -> Vec<Vec<usize>> {
+        watches.iter().map(|(owners, _)| owners.clone()).collect()
+    }
 
-        assert_eq!(actions.len(), 2);
-        assert!(actions.contains(&EndpointAction::Remove("10.0.0.1:50051".parse().unwrap())));
-        assert!(actions.contains(&EndpointAction::Remove("10.0.0.2:50051".parse().unwrap())));
-        assert!(known.is_empty());
+    #[tokio::test]
+    async fn watch_pods_shares_watch_per_namespace() {
+        let mut sources = sources(vec![
+            ServiceRef::new("svc-a", 50051_u16).namespace("tenant-a"),
+            ServiceRef::new("svc-b", 50051_u16).namespace("tenant-b"),
+            ServiceRef::new("svc-c", 50051_u16).namespace("tenant-a"),
+        ])
+        .await;
+
+        let watches = watch_pods(&mut sources, &test_client(), &annotation());
+        assert_eq!(owners(&watches), vec![vec![0, 2], vec![1]]);
+
+        // Services sharing a watch share its weights
+        let weights = |index: usize| &sources[index].tracker.weights;
+        assert!(Arc::ptr_eq(weights(0), weights(2)));
+        assert!(!Arc::ptr_eq(weights(0), weights(1)));
     }
 
-    #[test]
-    fn process_event_delete_skips_unknown_endpoints() {
-        let slice = EndpointSlice {
-            endpoints: vec![make_endpoint(vec!["10.0.0.1", "10.0.0.2"], Some(true))],
-            ..Default::default()
-        };
+    #[tokio::test]
+    async fn watch_pods_once_cluster_wide() {
+        let mut sources = sources(vec![
+            ServiceRef::new("svc-a", 50051_u16).namespace("tenant-a"),
+            ServiceRef::new("svc-b", 50051_u16).all_namespaces(NamespaceFilter::default()),
+        ])
+        .await;
 
-        let mut known = HashSet::new();
-        known.insert("10.0.0.1:50051".parse().unwrap());
-        // 10.0.0.2 is not known
+        let watches = watch_pods(&mut sources, &test_client(), &annotation());
+        assert_eq!(owners(&watches), vec![vec![0, 1]]);
+    }
 
-        let actions = process_event(&Event::Delete(slice), &mut known, &Port::Number(50051));
+    #[tokio::test]
+    async fn shared_weights_reweigh_every_source() {
+        let mut sources = sources(vec![
+            ServiceRef::new("svc-a", 50051_u16).namespace("default"),
+            ServiceRef::new("svc-b", 50051_u16).namespace("default"),
+        ])
+        .await;
+        watch_pods(&mut sources, &test_client(), &annotation());
+
+        for source in &mut sources {
+            let event = Event::Apply(weighted_pod_slice());
+            source.handle(&WatchEvent::Slices(Box::new(event)), ReadinessPolicy::Ready);
+        }
 
-        // Only 10.0.0.1 should be removed since 10.0.0.2 wasn't known
-        assert_eq!(actions.len(), 1);
-        assert!(actions.contains(&EndpointAction::Remove("10.0.0.1:50051".parse().unwrap())));
-        assert!(known.is_empty());
+        set_weight(&sources[0].tracker.weights, "5");
+        for source in &mut sources {
+            let actions = source.handle(&WatchEvent::Pods(true), ReadinessPolicy::Ready);
+            let [EndpointAction::Update(_, endpoint)] = actions.as_slice() else {
+                panic!("expected an update, got {actions:?}");
+            };
+
+            assert_eq!(endpoint.weight, 5);
+        }
     }
 
+    // slice_key tests
+
     #[test]
-    fn process_event_init_returns_empty() {
-        let mut known = HashSet::new();
-        let actions = process_event(&Event::Init, &mut known, &Port::Number(50051));
+    fn slice_key_prefers_uid() {
+        let mut slice = make_slice("svc-a", Vec::new());
+        slice.metadata.uid = Some("1234".to_string());
 
-        assert!(actions.is_empty());
+        assert_eq!(slice_key(&slice), "1234");
     }
 
     #[test]
-    fn process_event_init_done_returns_empty() {
-        let mut known = HashSet::new();
-        let actions = process_event(&Event::InitDone, &mut known, &Port::Number(50051));
-
-        assert!(actions.is_empty());
+    fn slice_key_falls_back_to_namespaced_name() {
+        let slice = make_slice("svc-a", Vec::new());
+        assert_eq!(slice_key(&slice), "default/svc-a");
     }
 }
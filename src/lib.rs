@@ -24,10 +24,10 @@
 //! // Create your own balance channel
 //! let (channel, tx) = Channel::balance_channel::<SocketAddr>(1024);
 //!
-//! // Start discovery - build function returns Endpoint for each address
+//! // Start discovery - build function returns Endpoint for each discovered endpoint
 //! let config = DiscoveryConfig::new("my-grpc-service", 50051);
-//! discover(config, tx, |addr| {
-//!     Endpoint::from_shared(format!("http://{addr}"))
+//! discover(config, tx, |endpoint| {
+//!     Endpoint::from_shared(format!("http://{}", endpoint.address))
 //!         .unwrap()
 //!         .connect_timeout(Duration::from_secs(5))
 //! });
@@ -36,6 +36,30 @@
 //! // let client = MyServiceClient::new(channel);
 //! ```
 
+mod balance;
+mod batch;
+mod endpoint;
+mod error;
+mod guard;
+mod handle;
 mod k8s;
+mod publisher;
+mod retry;
+mod select;
+mod subset;
+mod topology;
+mod weight;
 
-pub use k8s::{DiscoveryConfig, Port, discover};
+pub use balance::WeightedChannel;
+pub use endpoint::{DiscoveredEndpoint, DiscoveryKey, EndpointKey, TargetRef, Weighted};
+pub use error::{BoxError, Error};
+pub use guard::PanicThreshold;
+pub use handle::{DiscoveryHandle, DiscoveryStatus, NotReady};
+pub use k8s::{
+    DiscoveryConfig, NamespaceFilter, Port, ServiceRef, discover, discover_async, try_discover,
+};
+pub use retry::{ErrorKind, RetryPolicy};
+pub use select::ReadinessPolicy;
+pub use subset::{ClientId, Subset};
+pub use topology::{NodeLocal, NodeLocalMode, NodeSource, ZoneAware, ZoneSource};
+pub use weight::WeightSource;
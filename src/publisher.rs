@@ -0,0 +1,270 @@
+//! Sends endpoint changes to the balance channel.
+//!
+//! Endpoints are built when an endpoint is inserted. Failed builds are logged,
+//! counted and retried on the next update instead of stopping discovery.
+
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use tokio::sync::mpsc::Sender;
+use tonic::transport::Endpoint;
+use tonic::transport::channel::Change;
+use tracing::warn;
+
+use crate::endpoint::{DiscoveredEndpoint, DiscoveryKey};
+use crate::error::{BoxError, Error, Result};
+use crate::k8s::EndpointAction;
+
+/// Publishes endpoint actions to the channel, building endpoints on insertion.
+pub(crate) struct Publisher<K, F> {
+    /// Sender for endpoint changes.
+    tx: Sender<Change<K, Endpoint>>,
+
+    /// Function building an `Endpoint` for a discovered endpoint.
+    build: F,
+
+    /// Endpoints that failed to build, retried on the next update.
+    failed: HashMap<K, DiscoveredEndpoint>,
+
+    /// Endpoints sent to the channel and not removed since.
+    published: HashSet<K>,
+
+    /// Total number of failed builds.
+    build_failures: u64,
+}
+
+impl<K, F, Fut, E> Publisher<K, F>
+where
+    K: DiscoveryKey,
+    F: Fn(DiscoveredEndpoint) -> Fut,
+    Fut: Future<Output = std::result::Result<Endpoint, E>>,
+    E: Into<BoxError>,
+{
+    /// Creates a new publisher.
+    pub(crate) fn new(tx: Sender<Change<K, Endpoint>>, build: F) -> Self {
+        Self {
+            tx,
+            build,
+            failed: HashMap::new(),
+            published: HashSet::new(),
+            build_failures: 0,
+        }
+    }
+
+    /// Returns the number of endpoints in the channel.
+    pub(crate) fn published(&self) -> usize {
+        self.published.len()
+    }
+
+    /// Returns the total number of failed builds.
+    pub(crate) fn build_failures(&self) -> u64 {
+        self.build_failures
+    }
+
+    /// Sends the changes for the given actions and retries previously failed builds.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ChannelClosed`] if the receiver has been dropped.
+    pub(crate) async fn publish(&mut self, actions: Vec<EndpointAction<K>>) -> Result<()> {
+        let retries: Vec<K> = self.failed.keys().cloned().collect();
+
+        for action in actions {
+            match action {
+                EndpointAction::Insert(key, endpoint) => self.insert(key, *endpoint).await?,
+                EndpointAction::Update(key, endpoint) => {
+                    // Built endpoints are kept; only pending rebuilds see the new metadata
+                    if let Some(failed) = self.failed.get_mut(&key) {
+                        *failed = *endpoint;
+                    }
+                }
+                EndpointAction::Remove(key) => {
+                    // Endpoints that were never built have nothing to remove
+                    if self.failed.remove(&key).is_none() {
+                        self.published.remove(&key);
+                        send(&self.tx, Change::Remove(key)).await?;
+                    }
+                }
+            }
+        }
+
+        for key in retries {
+            if let Some(endpoint) = self.failed.remove(&key) {
+                self.insert(key, endpoint).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Builds the endpoint and sends it, remembering failures.
+    async fn insert(&mut self, key: K, endpoint: DiscoveredEndpoint) -> Result<()> {
+        self.failed.remove(&key);
+
+        match (self.build)(endpoint.clone()).await {
+            Ok(built) => {
+                self.published.insert(key.clone());
+                send(&self.tx, Change::Insert(key, built)).await
+            }
+            Err(e) => {
+                let err: BoxError = e.into();
+                warn!(
+                    "failed to build endpoint {}, retrying on the next update: {err}",
+                    endpoint.address
+                );
+
+                self.build_failures += 1;
+                self.failed.insert(key, endpoint);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Sends a change to the channel.
+///
+/// Takes the sender rather than the publisher, which need not be `Sync`.
+async fn send<K>(tx: &Sender<Change<K, Endpoint>>, change: Change<K, Endpoint>) -> Result<()> {
+    tx.send(change).await.map_err(|_| {
+        warn!("channel closed, stopping Kubernetes watcher");
+        Error::ChannelClosed
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::{Ready, ready};
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use tokio::sync::mpsc::{self, Receiver};
+
+    use super::*;
+
+    type Build = Box<dyn Fn(DiscoveredEndpoint) -> Ready<std::result::Result<Endpoint, BoxError>>>;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    fn insert(s: &str) -> EndpointAction<SocketAddr> {
+        EndpointAction::Insert(addr(s), Box::new(DiscoveredEndpoint::new(addr(s))))
+    }
+
+    // Builds endpoints while `healthy` is set, fails otherwise
+    fn publisher(
+        healthy: Arc<AtomicBool>,
+    ) -> (
+        Publisher<SocketAddr, Build>,
+        Receiver<Change<SocketAddr, Endpoint>>,
+    ) {
+        let (tx, rx) = mpsc::channel(16);
+        let build: Build = Box::new(move |endpoint| {
+            ready(if healthy.load(Ordering::SeqCst) {
+                Ok(Endpoint::from_shared(format!("http://{}", endpoint.address)).unwrap())
+            } else {
+                Err("credentials unavailable".into())
+            })
+        });
+
+        (Publisher::new(tx, build), rx)
+    }
+
+    fn drain(rx: &mut Receiver<Change<SocketAddr, Endpoint>>) -> Vec<String> {
+        let mut changes = Vec::new();
+        while let Ok(change) = rx.try_recv() {
+            changes.push(match change {
+                Change::Insert(addr, _) => format!("insert {addr}"),
+                Change::Remove(addr) => format!("remove {addr}"),
+            });
+        }
+
+        changes
+    }
+
+    #[tokio::test]
+    async fn publish_sends_inserts_and_removes() {
+        let (mut publisher, mut rx) = publisher(Arc::new(AtomicBool::new(true)));
+
+        publisher
+            .publish(vec![insert("10.0.0.1:50051")])
+            .await
+            .unwrap();
+
+        publisher
+            .publish(vec![EndpointAction::Remove(addr("10.0.0.1:50051"))])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            drain(&mut rx),
+            vec!["insert 10.0.0.1:50051", "remove 10.0.0.1:50051"]
+        );
+    }
+
+    #[tokio::test]
+    async fn publish_counts_failed_builds() {
+        let (mut publisher, mut rx) = publisher(Arc::new(AtomicBool::new(false)));
+
+        publisher
+            .publish(vec![insert("10.0.0.1:50051")])
+            .await
+            .unwrap();
+
+        assert!(drain(&mut rx).is_empty());
+        assert_eq!(publisher.failed.len(), 1);
+        assert_eq!(publisher.build_failures(), 1);
+    }
+
+    #[tokio::test]
+    async fn publish_retries_failed_builds_on_next_update() {
+        let healthy = Arc::new(AtomicBool::new(false));
+        let (mut publisher, mut rx) = publisher(healthy.clone());
+
+        publisher
+            .publish(vec![insert("10.0.0.1:50051")])
+            .await
+            .unwrap();
+
+        // Still failing: retried and counted again
+        publisher.publish(Vec::new()).await.unwrap();
+        assert_eq!(publisher.build_failures(), 2);
+
+        healthy.store(true, Ordering::SeqCst);
+        publisher.publish(Vec::new()).await.unwrap();
+
+        assert_eq!(drain(&mut rx), vec!["insert 10.0.0.1:50051"]);
+        assert_eq!(publisher.failed.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn publish_drops_failed_build_on_remove() {
+        let healthy = Arc::new(AtomicBool::new(false));
+        let (mut publisher, mut rx) = publisher(healthy.clone());
+
+        publisher
+            .publish(vec![insert("10.0.0.1:50051")])
+            .await
+            .unwrap();
+
+        healthy.store(true, Ordering::SeqCst);
+        publisher
+            .publish(vec![EndpointAction::Remove(addr("10.0.0.1:50051"))])
+            .await
+            .unwrap();
+
+        // The endpoint was never inserted, so neither an insert nor a remove is sent
+        assert!(drain(&mut rx).is_empty());
+        assert_eq!(publisher.failed.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn publish_fails_when_channel_closed() {
+        let (mut publisher, rx) = publisher(Arc::new(AtomicBool::new(true)));
+        drop(rx);
+
+        let result = publisher.publish(vec![insert("10.0.0.1:50051")]).await;
+
+        assert!(matches!(result, Err(Error::ChannelClosed)));
+    }
+}
@@ -0,0 +1,168 @@
+//! Error classification and retry policy for the discovery task.
+//!
+//! Failures while constructing the Kubernetes client or watching `EndpointSlice`
+//! resources are classified into broad kinds. Each failure is retried with
+//! exponential backoff unless its kind is configured as fatal, or the maximum
+//! number of consecutive failures has been reached.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// Broad classification of discovery failures.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// The Kubernetes client could not be configured (e.g. no kubeconfig or service account).
+    Config,
+    /// The API server denied access, usually due to missing RBAC permissions.
+    Forbidden,
+    /// The requested resource type does not exist on the API server.
+    NotFound,
+    /// A transient failure such as a network error, timeout or server error.
+    Transient,
+}
+
+/// Retry policy for discovery failures.
+///
+/// By default, every failure is retried indefinitely with exponential backoff
+/// between 500 milliseconds and 30 seconds. While retrying, the last known
+/// endpoints remain in the channel.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Delay before the first retry.
+    pub initial_backoff: Duration,
+
+    /// Upper bound for the delay between retries.
+    pub max_backoff: Duration,
+
+    /// Maximum number of consecutive failures before giving up.
+    /// If `None`, retries indefinitely.
+    pub max_failures: Option<u32>,
+
+    /// Kinds of errors that stop discovery immediately instead of being retried.
+    pub fatal: HashSet<ErrorKind>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            max_failures: None,
+            fatal: HashSet::new(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Sets the delay before the first retry.
+    #[must_use]
+    pub fn initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// Sets the upper bound for the delay between retries.
+    #[must_use]
+    pub fn max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// Sets the maximum number of consecutive failures before giving up.
+    #[must_use]
+    pub fn max_failures(mut self, failures: u32) -> Self {
+        self.max_failures = Some(failures);
+        self
+    }
+
+    /// Marks a kind of error as fatal, stopping discovery when it occurs.
+    #[must_use]
+    pub fn fatal(mut self, kind: ErrorKind) -> Self {
+        self.fatal.insert(kind);
+        self
+    }
+
+    /// Returns the delay before the next retry, or `None` if discovery should give up.
+    ///
+    /// `failures` is the number of consecutive failures so far, including the current one.
+    pub(crate) fn next_delay(&self, kind: ErrorKind, failures: u32) -> Option<Duration> {
+        if self.fatal.contains(&kind) {
+            return None;
+        }
+
+        if self.max_failures.is_some_and(|max| failures >= max) {
+            return None;
+        }
+
+        let exponent = failures.saturating_sub(1).min(31);
+        let delay = self.initial_backoff.saturating_mul(1 << exponent);
+        Some(delay.min(self.max_backoff))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RetryPolicy tests
+
+    #[test]
+    fn policy_default_retries_everything() {
+        let policy = RetryPolicy::default();
+
+        for kind in [
+            ErrorKind::Config,
+            ErrorKind::Forbidden,
+            ErrorKind::NotFound,
+            ErrorKind::Transient,
+        ] {
+            assert!(policy.next_delay(kind, 1000).is_some());
+        }
+    }
+
+    #[test]
+    fn policy_backoff_doubles_up_to_max() {
+        let policy = RetryPolicy::default()
+            .initial_backoff(Duration::from_secs(1))
+            .max_backoff(Duration::from_secs(5));
+
+        let delays: Vec<_> = (1..=5)
+            .map(|n| policy.next_delay(ErrorKind::Transient, n).unwrap())
+            .collect();
+
+        assert_eq!(
+            delays,
+            vec![
+                Duration::from_secs(1),
+                Duration::from_secs(2),
+                Duration::from_secs(4),
+                Duration::from_secs(5),
+                Duration::from_secs(5),
+            ]
+        );
+    }
+
+    #[test]
+    fn policy_backoff_does_not_overflow() {
+        let policy = RetryPolicy::default();
+        let delay = policy.next_delay(ErrorKind::Transient, u32::MAX).unwrap();
+
+        assert_eq!(delay, policy.max_backoff);
+    }
+
+    #[test]
+    fn policy_fatal_kind_gives_up() {
+        let policy = RetryPolicy::default().fatal(ErrorKind::Forbidden);
+
+        assert!(policy.next_delay(ErrorKind::Forbidden, 1).is_none());
+        assert!(policy.next_delay(ErrorKind::Transient, 1).is_some());
+    }
+
+    #[test]
+    fn policy_max_failures_gives_up() {
+        let policy = RetryPolicy::default().max_failures(3);
+
+        assert!(policy.next_delay(ErrorKind::Transient, 2).is_some());
+        assert!(policy.next_delay(ErrorKind::Transient, 3).is_none());
+    }
+}
@@ -0,0 +1,515 @@
+//! Selection of the endpoints sent to the channel.
+//!
+//! The trackers report every candidate endpoint of the watched Services. The
+//! selection decides which candidates are published, e.g. falling back to
+//! terminating endpoints when no ready endpoints remain, preferring endpoints
+//! close to the client, or picking the client's subset, and diffs the result
+//! against the endpoints published so far.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::endpoint::{DiscoveredEndpoint, DiscoveryKey};
+use crate::k8s::EndpointAction;
+use crate::subset::Subsetter;
+use crate::topology::{NodePreference, ZonePreference};
+
+/// Which endpoints are sent to the channel, based on their conditions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReadinessPolicy {
+    /// Only ready endpoints. Terminating pods are removed as soon as they stop
+    /// being ready.
+    #[default]
+    Ready,
+
+    /// Serving endpoints, including terminating pods that are still serving,
+    /// so in-flight traffic can drain while a pod shuts down gracefully.
+    Serving,
+
+    /// Ready endpoints, falling back to serving terminating endpoints when no
+    /// ready endpoints exist, like kube-proxy does.
+    TerminatingFallback,
+
+    /// All addresses regardless of their conditions, e.g. so that `StatefulSet`
+    /// pods can reach each other while bootstrapping.
+    All,
+}
+
+impl ReadinessPolicy {
+    /// Returns whether an endpoint may be selected under this policy.
+    pub(crate) fn admits(self, endpoint: &DiscoveredEndpoint) -> bool {
+        match self {
+            Self::Ready => endpoint.ready,
+            Self::Serving | Self::TerminatingFallback => endpoint.serving,
+            Self::All => true,
+        }
+    }
+}
+
+/// Selects the endpoints to publish from the candidates reported by the tracker.
+#[derive(Debug)]
+pub(crate) struct Selection<K> {
+    /// Policy deciding which candidates are selected.
+    readiness: ReadinessPolicy,
+
+    /// Preference for the endpoints of the client's node, if node-local.
+    node: Option<NodePreference>,
+
+    /// Preference for the endpoints of the client's zone, if zone-aware.
+    zone: Option<ZonePreference>,
+
+    /// Picks the client's subset of the endpoints, if subsetting.
+    subset: Option<Subsetter>,
+
+    /// Every candidate endpoint, keyed by channel key and then by the index of the
+    /// Service contributing it.
+    candidates: HashMap<K, BTreeMap<usize, DiscoveredEndpoint>>,
+
+    /// Keys of the endpoints currently published.
+    selected: HashSet<K>,
+}
+
+impl<K: DiscoveryKey> Selection<K> {
+    /// Creates an empty selection.
+    pub(crate) fn new(readiness: ReadinessPolicy) -> Self {
+        Self {
+            readiness,
+            node: None,
+            zone: None,
+            subset: None,
+            candidates: HashMap::new(),
+            selected: HashSet::new(),
+        }
+    }
+
+    /// Prefers the endpoints of the client's node.
+    pub(crate) fn prefer_node(mut self, node: NodePreference) -> Self {
+        self.node = Some(node);
+        self
+    }
+
+    /// Prefers the endpoints of the client's zone once it is known, and returns the
+    /// changes to publish.
+    pub(crate) fn prefer_zone(&mut self, zone: ZonePreference) -> Vec<EndpointAction<K>> {
+        self.zone = Some(zone);
+        self.publish(&HashSet::new())
+    }
+
+    /// Publishes only the client's subset of the endpoints.
+    pub(crate) fn subset(mut self, subset: Subsetter) -> Self {
+        self.subset = Some(subset);
+        self
+    }
+
+    /// Returns the number of endpoints currently published.
+    pub(crate) fn len(&self) -> usize {
+        self.selected.len()
+    }
+
+    /// Applies candidate changes of a Service and returns the changes to publish.
+    ///
+    /// A candidate contributed by several Services is only removed once the last
+    /// of them removes it.
+    pub(crate) fn update(
+        &mut self,
+        source: usize,
+        actions: Vec<EndpointAction<K>>,
+    ) -> Vec<EndpointAction<K>> {
+        if actions.is_empty() {
+            return Vec::new();
+        }
+
+        let mut updated = HashSet::new();
+        for action in actions {
+            match action {
+                EndpointAction::Insert(key, endpoint) | EndpointAction::Update(key, endpoint) => {
+                    let sources = self.candidates.entry(key.clone()).or_default();
+                    sources.insert(source, *endpoint);
+                    updated.insert(key);
+                }
+
+                EndpointAction::Remove(key) => {
+                    if let Some(sources) = self.candidates.get_mut(&key) {
+                        sources.remove(&source);
+                        if sources.is_empty() {
+                            self.candidates.remove(&key);
+                        }
+                    }
+
+                    updated.insert(key);
+                }
+            }
+        }
+
+        self.publish(&updated)
+    }
+
+    /// Selects the endpoints to publish and returns the changes to the endpoints
+    /// published so far, updating those whose metadata was updated.
+    fn publish(&mut self, updated: &HashSet<K>) -> Vec<EndpointAction<K>> {
+        let selected = self.select();
+        let mut changes = Vec::new();
+
+        for key in &self.selected {
+            if !selected.contains(key) {
+                changes.push(EndpointAction::Remove(key.clone()));
+            }
+        }
+
+        for key in &selected {
+            let endpoint = || Box::new(self.candidate(key).clone());
+            if !self.selected.contains(key) {
+                changes.push(EndpointAction::Insert(key.clone(), endpoint()));
+            } else if updated.contains(key) {
+                changes.push(EndpointAction::Update(key.clone(), endpoint()));
+            }
+        }
+
+        self.selected = selected;
+        changes
+    }
+
+    /// Returns a candidate, as reported by the first Service contributing it.
+    fn candidate(&self, key: &K) -> &DiscoveredEndpoint {
+        let sources = &self.candidates[key];
+        sources
+            .values()
+            .next()
+            .expect("candidates have at least one source")
+    }
+
+    /// Returns the keys of the candidates that should be published.
+    fn select(&self) -> HashSet<K> {
+        let candidates = self.candidates.keys().map(|key| (key, self.candidate(key)));
+
+        // Terminating endpoints are only used once no ready endpoints remain
+        let ready_only = self.readiness == ReadinessPolicy::TerminatingFallback
+            && candidates.clone().any(|(_, endpoint)| endpoint.ready);
+
+        let admitted: Vec<_> = candidates
+            .filter(|(_, endpoint)| {
+                if ready_only {
+                    endpoint.ready
+                } else {
+                    self.readiness.admits(endpoint)
+                }
+            })
+            .collect();
+
+        // The node's endpoints take precedence over those of its zone
+        let local = self.node.as_ref().and_then(|node| node.apply(&admitted));
+        let admitted = match (local, &self.zone) {
+            (Some(local), _) => local,
+            (None, Some(zone)) => zone.apply(admitted),
+            (None, None) => admitted,
+        };
+
+        let admitted = match &self.subset {
+            Some(subset) => subset.apply(admitted),
+            None => admitted,
+        };
+
+        admitted.into_iter().map(|(key, _)| key.clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+    use crate::topology::NodeLocalMode;
+
+    fn endpoint(addr: &str, ready: bool, serving: bool, terminating: bool) -> DiscoveredEndpoint {
+        let mut endpoint = DiscoveredEndpoint::new(addr.parse().unwrap());
+        endpoint.ready = ready;
+        endpoint.serving = serving;
+        endpoint.terminating = terminating;
+        endpoint
+    }
+
+    fn ready(addr: &str) -> DiscoveredEndpoint {
+        endpoint(addr, true, true, false)
+    }
+
+    fn terminating(addr: &str) -> DiscoveredEndpoint {
+        endpoint(addr, false, true, true)
+    }
+
+    fn not_ready(addr: &str) -> DiscoveredEndpoint {
+        endpoint(addr, false, false, false)
+    }
+
+    fn insert(endpoint: DiscoveredEndpoint) -> EndpointAction<SocketAddr> {
+        EndpointAction::Insert(endpoint.address, Box::new(endpoint))
+    }
+
+    fn update(endpoint: DiscoveredEndpoint) -> EndpointAction<SocketAddr> {
+        EndpointAction::Update(endpoint.address, Box::new(endpoint))
+    }
+
+    fn remove(addr: &str) -> EndpointAction<SocketAddr> {
+        EndpointAction::Remove(addr.parse().unwrap())
+    }
+
+    // Renders actions in a stable order for comparison
+    fn render(actions: &[EndpointAction<SocketAddr>]) -> Vec<String> {
+        let mut rendered: Vec<String> = actions
+            .iter()
+            .map(|action| match action {
+                EndpointAction::Insert(key, _) => format!("insert {key}"),
+                EndpointAction::Update(key, _) => format!("update {key}"),
+                EndpointAction::Remove(key) => format!("remove {key}"),
+            })
+            .collect();
+
+        rendered.sort();
+        rendered
+    }
+
+    // ReadinessPolicy tests
+
+    #[test]
+    fn policy_default_is_ready() {
+        assert_eq!(ReadinessPolicy::default(), ReadinessPolicy::Ready);
+    }
+
+    #[test]
+    fn policy_admits() {
+        let cases = [
+            (ReadinessPolicy::Ready, [true, false, false]),
+            (ReadinessPolicy::Serving, [true, true, false]),
+            (ReadinessPolicy::TerminatingFallback, [true, true, false]),
+            (ReadinessPolicy::All, [true, true, true]),
+        ];
+
+        for (policy, expected) in cases {
+            let admitted = [
+                ready("10.0.0.1:80"),
+                terminating("10.0.0.2:80"),
+                not_ready("10.0.0.3:80"),
+            ]
+            .map(|endpoint| policy.admits(&endpoint));
+
+            assert_eq!(admitted, expected, "{policy:?}");
+        }
+    }
+
+    // Selection tests
+
+    #[test]
+    fn selection_ready_skips_terminating() {
+        let mut selection = Selection::new(ReadinessPolicy::Ready);
+
+        let changes = selection.update(
+            0,
+            vec![
+                insert(ready("10.0.0.1:80")),
+                insert(terminating("10.0.0.2:80")),
+            ],
+        );
+
+        assert_eq!(render(&changes), vec!["insert 10.0.0.1:80"]);
+        assert_eq!(selection.len(), 1);
+    }
+
+    #[test]
+    fn selection_serving_keeps_terminating_endpoint() {
+        let mut selection = Selection::new(ReadinessPolicy::Serving);
+        selection.update(0, vec![insert(ready("10.0.0.1:80"))]);
+
+        // The pod starts terminating but still serves in-flight traffic
+        let changes = selection.update(0, vec![update(terminating("10.0.0.1:80"))]);
+        assert_eq!(render(&changes), vec!["update 10.0.0.1:80"]);
+
+        // The pod stops serving
+        let changes = selection.update(0, vec![update(not_ready("10.0.0.1:80"))]);
+        assert_eq!(render(&changes), vec!["remove 10.0.0.1:80"]);
+    }
+
+    #[test]
+    fn selection_falls_back_to_terminating_without_ready_endpoints() {
+        let mut selection = Selection::new(ReadinessPolicy::TerminatingFallback);
+
+        let changes = selection.update(
+            0,
+            vec![
+                insert(ready("10.0.0.1:80")),
+                insert(terminating("10.0.0.2:80")),
+            ],
+        );
+        assert_eq!(render(&changes), vec!["insert 10.0.0.1:80"]);
+
+        // The last ready endpoint starts terminating as well
+        let changes = selection.update(0, vec![update(terminating("10.0.0.1:80"))]);
+        assert_eq!(
+            render(&changes),
+            vec!["insert 10.0.0.2:80", "update 10.0.0.1:80"]
+        );
+
+        // A ready endpoint comes back, so the terminating ones are dropped
+        let changes = selection.update(0, vec![insert(ready("10.0.0.3:80"))]);
+        assert_eq!(
+            render(&changes),
+            vec![
+                "insert 10.0.0.3:80",
+                "remove 10.0.0.1:80",
+                "remove 10.0.0.2:80"
+            ]
+        );
+    }
+
+    #[test]
+    fn selection_all_includes_not_ready() {
+        let mut selection = Selection::new(ReadinessPolicy::All);
+
+        let changes = selection.update(0, vec![insert(not_ready("10.0.0.1:80"))]);
+        assert_eq!(render(&changes), vec!["insert 10.0.0.1:80"]);
+    }
+
+    #[test]
+    fn selection_removes_deleted_candidates() {
+        let mut selection = Selection::new(ReadinessPolicy::Ready);
+        selection.update(0, vec![insert(ready("10.0.0.1:80"))]);
+
+        let changes = selection.update(0, vec![remove("10.0.0.1:80")]);
+        assert_eq!(render(&changes), vec!["remove 10.0.0.1:80"]);
+        assert_eq!(selection.len(), 0);
+    }
+
+    #[test]
+    fn selection_keeps_endpoint_contributed_by_another_service() {
+        let mut selection = Selection::new(ReadinessPolicy::Ready);
+
+        let changes = selection.update(0, vec![insert(ready("10.0.0.1:80"))]);
+        assert_eq!(render(&changes), vec!["insert 10.0.0.1:80"]);
+
+        // The same endpoint is behind a second Service
+        let changes = selection.update(1, vec![insert(ready("10.0.0.1:80"))]);
+        assert_eq!(render(&changes), vec!["update 10.0.0.1:80"]);
+
+        let changes = selection.update(0, vec![remove("10.0.0.1:80")]);
+        assert_eq!(render(&changes), vec!["update 10.0.0.1:80"]);
+        assert_eq!(selection.len(), 1);
+
+        let changes = selection.update(1, vec![remove("10.0.0.1:80")]);
+        assert_eq!(render(&changes), vec!["remove 10.0.0.1:80"]);
+    }
+
+    fn zone(zone: &str) -> ZonePreference {
+        ZonePreference {
+            zone: zone.to_string(),
+            min_endpoints: 1,
+        }
+    }
+
+    #[test]
+    fn selection_narrows_to_zone_once_known() {
+        let mut in_zone = ready("10.0.0.1:80");
+        in_zone.zone = Some("a".to_string());
+        let mut other_zone = ready("10.0.0.2:80");
+        other_zone.zone = Some("b".to_string());
+
+        let mut selection = Selection::new(ReadinessPolicy::Ready);
+        let changes = selection.update(0, vec![insert(in_zone), insert(other_zone)]);
+        assert_eq!(changes.len(), 2);
+
+        let changes = selection.prefer_zone(zone("a"));
+        assert_eq!(render(&changes), vec!["remove 10.0.0.2:80"]);
+        assert_eq!(selection.len(), 1);
+    }
+
+    #[test]
+    fn selection_prefers_zone_until_too_few_ready_endpoints() {
+        let in_zone = |addr: &str, zone: &str| {
+            let mut endpoint = ready(addr);
+            endpoint.zone = Some(zone.to_string());
+            endpoint
+        };
+
+        let mut selection = Selection::new(ReadinessPolicy::Ready);
+        selection.prefer_zone(zone("a"));
+
+        let changes = selection.update(
+            0,
+            vec![
+                insert(in_zone("10.0.0.1:80", "a")),
+                insert(in_zone("10.0.0.2:80", "b")),
+            ],
+        );
+        assert_eq!(render(&changes), vec!["insert 10.0.0.1:80"]);
+
+        // The only endpoint in the zone stops being ready
+        let mut not_ready = in_zone("10.0.0.1:80", "a");
+        not_ready.ready = false;
+
+        let changes = selection.update(0, vec![update(not_ready)]);
+        assert_eq!(
+            render(&changes),
+            vec!["insert 10.0.0.2:80", "remove 10.0.0.1:80"]
+        );
+    }
+
+    #[test]
+    fn selection_prefers_node_over_zone() {
+        let located = |addr: &str, node: &str, zone: &str| {
+            let mut endpoint = ready(addr);
+            endpoint.node_name = Some(node.to_string());
+            endpoint.zone = Some(zone.to_string());
+            endpoint
+        };
+
+        let mut selection = Selection::new(ReadinessPolicy::Ready).prefer_node(NodePreference {
+            node: "node-1".to_string(),
+            mode: NodeLocalMode::Prefer,
+        });
+        selection.prefer_zone(zone("a"));
+
+        let changes = selection.update(
+            0,
+            vec![
+                insert(located("10.0.0.1:80", "node-1", "a")),
+                insert(located("10.0.0.2:80", "node-2", "a")),
+                insert(located("10.0.0.3:80", "node-3", "b")),
+            ],
+        );
+        assert_eq!(render(&changes), vec!["insert 10.0.0.1:80"]);
+
+        // Without an endpoint on the node, the zone's endpoints are used
+        let changes = selection.update(0, vec![remove("10.0.0.1:80")]);
+        assert_eq!(
+            render(&changes),
+            vec!["insert 10.0.0.2:80", "remove 10.0.0.1:80"]
+        );
+    }
+
+    #[test]
+    fn selection_subset_replaces_removed_endpoint() {
+        let mut selection = Selection::new(ReadinessPolicy::Ready).subset(Subsetter {
+            client: "client-1".to_string(),
+            size: 2,
+        });
+
+        let addrs = ["10.0.0.1:80", "10.0.0.2:80", "10.0.0.3:80", "10.0.0.4:80"];
+        let changes = selection.update(0, addrs.iter().map(|a| insert(ready(a))).collect());
+        assert_eq!(changes.len(), 2);
+        assert_eq!(selection.len(), 2);
+
+        // A removed endpoint of the subset is replaced by the next in rank
+        let EndpointAction::Insert(selected, _) = &changes[0] else {
+            panic!("expected an insert, got {changes:?}");
+        };
+
+        let changes = selection.update(0, vec![remove(&selected.to_string())]);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(selection.len(), 2);
+    }
+
+    #[test]
+    fn selection_ignores_removal_by_service_not_contributing() {
+        let mut selection = Selection::new(ReadinessPolicy::Ready);
+        selection.update(0, vec![insert(ready("10.0.0.1:80"))]);
+
+        selection.update(1, vec![remove("10.0.0.1:80")]);
+        assert_eq!(selection.len(), 1);
+    }
+}
@@ -0,0 +1,230 @@
+//! Deterministic subsetting.
+//!
+//! With many clients and many backends, connecting every client to every backend
+//! opens a large number of connections. Subsetting connects each client to a
+//! bounded subset of the endpoints instead, chosen by rendezvous hashing of the
+//! client's identity and the endpoint addresses. Subsets are stable across
+//! restarts, spread evenly across backends, and change minimally when backends
+//! are added or removed.
+
+use std::cmp::Reverse;
+use std::hash::Hasher;
+use std::net::SocketAddr;
+
+use crate::endpoint::DiscoveredEndpoint;
+
+/// Where the identity of the client is read from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ClientId {
+    /// The given identity.
+    Name(String),
+
+    /// The environment variable with the given name, holding the identity.
+    Env(String),
+}
+
+impl Default for ClientId {
+    /// The `HOSTNAME` environment variable, which holds the pod name in Kubernetes.
+    fn default() -> Self {
+        Self::Env("HOSTNAME".to_string())
+    }
+}
+
+impl ClientId {
+    /// Returns the identity of the client, or `None` if it is not known.
+    pub(crate) fn resolve(&self) -> Option<String> {
+        match self {
+            Self::Name(name) => Some(name.clone()),
+            Self::Env(var) => std::env::var(var).ok().filter(|value| !value.is_empty()),
+        }
+    }
+}
+
+/// Configuration of subsetting.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Subset {
+    /// Maximum number of endpoints sent to the channel (at least 1).
+    pub size: usize,
+
+    /// Where the identity of the client is read from.
+    pub client_id: ClientId,
+}
+
+impl Subset {
+    /// Creates a subsetting configuration with the given subset size, identifying
+    /// the client by its pod name.
+    #[must_use]
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            client_id: ClientId::default(),
+        }
+    }
+
+    /// Sets where the identity of the client is read from.
+    #[must_use]
+    pub fn client_id(mut self, client_id: ClientId) -> Self {
+        self.client_id = client_id;
+        self
+    }
+}
+
+/// Selects the subset of endpoints of a client.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Subsetter {
+    /// The identity of the client.
+    pub(crate) client: String,
+
+    /// Maximum number of endpoints selected.
+    pub(crate) size: usize,
+}
+
+impl Subsetter {
+    /// Returns the endpoints ranking highest for the client.
+    pub(crate) fn apply<'a, K>(
+        &self,
+        mut endpoints: Vec<(&'a K, &'a DiscoveredEndpoint)>,
+    ) -> Vec<(&'a K, &'a DiscoveredEndpoint)> {
+        let size = self.size.max(1);
+        if endpoints.len() > size {
+            endpoints.sort_by_cached_key(|(_, endpoint)| Reverse(self.score(endpoint.address)));
+            endpoints.truncate(size);
+        }
+
+        endpoints
+    }
+
+    /// Returns the rendezvous score of an endpoint for the client.
+    fn score(&self, address: SocketAddr) -> u64 {
+        // Hashes text rather than `Hash` output, which may differ between builds
+        let mut hasher = Fnv1a::default();
+        hasher.write(self.client.as_bytes());
+        hasher.write(&[0xff]);
+        hasher.write(address.to_string().as_bytes());
+        hasher.finish()
+    }
+}
+
+/// The 64-bit FNV-1a hash, which unlike the standard library's hasher is
+/// guaranteed to be stable, so that all clients rank endpoints alike.
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn finish(&self) -> u64 {
+        // Mixes the bits, as FNV-1a alone distributes similar inputs poorly
+        let mut hash = self.0;
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        hash ^= hash >> 33;
+        hash
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    fn endpoints(count: usize) -> Vec<DiscoveredEndpoint> {
+        (0..count)
+            .map(|i| {
+                DiscoveredEndpoint::new(format!("10.0.{}.{}:80", i / 250, i % 250).parse().unwrap())
+            })
+            .collect()
+    }
+
+    // Applies the subsetter and returns the selected addresses
+    fn subset(subsetter: &Subsetter, endpoints: &[DiscoveredEndpoint]) -> HashSet<SocketAddr> {
+        let keys: Vec<SocketAddr> = endpoints.iter().map(|e| e.address).collect();
+        let pairs = keys.iter().zip(endpoints).collect();
+
+        subsetter
+            .apply(pairs)
+            .into_iter()
+            .map(|(key, _)| *key)
+            .collect()
+    }
+
+    fn subsetter(client: &str, size: usize) -> Subsetter {
+        Subsetter {
+            client: client.to_string(),
+            size,
+        }
+    }
+
+    // Subset tests
+
+    #[test]
+    fn subset_defaults_to_hostname() {
+        let subset = Subset::new(10);
+
+        assert_eq!(subset.size, 10);
+        assert_eq!(subset.client_id, ClientId::Env("HOSTNAME".to_string()));
+    }
+
+    #[test]
+    fn client_id_resolves_name() {
+        let client_id = ClientId::Name("client-1".to_string());
+        assert_eq!(client_id.resolve().as_deref(), Some("client-1"));
+
+        let client_id = ClientId::Env("TONIC_LB_K8S_TEST_UNSET_CLIENT".to_string());
+        assert_eq!(client_id.resolve(), None);
+    }
+
+    // Subsetter tests
+
+    #[test]
+    fn subsetter_keeps_small_sets() {
+        let endpoints = endpoints(3);
+        assert_eq!(subset(&subsetter("client-1", 5), &endpoints).len(), 3);
+    }
+
+    #[test]
+    fn subsetter_is_deterministic() {
+        let endpoints = endpoints(50);
+        let selected = subset(&subsetter("client-1", 5), &endpoints);
+
+        assert_eq!(selected.len(), 5);
+        assert_eq!(subset(&subsetter("client-1", 5), &endpoints), selected);
+        assert_ne!(subset(&subsetter("client-2", 5), &endpoints), selected);
+    }
+
+    #[test]
+    fn subsetter_changes_minimally_when_scaling() {
+        let before = subset(&subsetter("client-1", 5), &endpoints(50));
+        let after = subset(&subsetter("client-1", 5), &endpoints(51));
+
+        // At most the new endpoint replaces one of the previous subset
+        assert!(before.intersection(&after).count() >= 4);
+    }
+
+    #[test]
+    fn subsetter_spreads_clients_across_endpoints() {
+        let endpoints = endpoints(20);
+        let mut load = vec![0_u32; 20];
+
+        for client in 0..200 {
+            for address in subset(&subsetter(&format!("client-{client}"), 5), &endpoints) {
+                let index = endpoints.iter().position(|e| e.address == address).unwrap();
+                load[index] += 1;
+            }
+        }
+
+        // Each endpoint serves about 50 of the 200 clients
+        assert!(load.iter().all(|l| (25..75).contains(l)), "{load:?}");
+    }
+}
@@ -0,0 +1,504 @@
+//! Topology-aware routing.
+//!
+//! Kubernetes publishes the node and zone of each endpoint and, for Services with
+//! topology-aware routing or `trafficDistribution: PreferClose`, hints telling
+//! which zones should consume it. Zone-aware discovery keeps traffic within the
+//! client's own zone using these, as long as enough endpoints are available there.
+//! Node-local discovery goes further and keeps traffic on the client's own node.
+
+use k8s_openapi::api::core::v1::Node;
+use kube::{Api, Client};
+
+use crate::endpoint::DiscoveredEndpoint;
+use crate::error::{Error, Result};
+
+/// Label holding the zone of a `Node`.
+const ZONE_LABEL: &str = "topology.kubernetes.io/zone";
+
+/// Where the name of the client's `Node` is read from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NodeSource {
+    /// The given node name.
+    Name(String),
+
+    /// The environment variable with the given name, holding the node name, e.g.
+    /// `NODE_NAME` set through the downward API.
+    Env(String),
+}
+
+impl Default for NodeSource {
+    fn default() -> Self {
+        Self::Env("NODE_NAME".to_string())
+    }
+}
+
+impl NodeSource {
+    /// Returns the name of the client's node, or `None` if it is not known.
+    pub(crate) fn resolve(&self) -> Option<String> {
+        match self {
+            Self::Name(name) => Some(name.clone()),
+            Self::Env(var) => env_var(var),
+        }
+    }
+}
+
+/// Where the zone of the client is read from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ZoneSource {
+    /// The given zone.
+    Zone(String),
+
+    /// The environment variable with the given name, holding the zone.
+    Env(String),
+
+    /// The `topology.kubernetes.io/zone` label of the client's `Node`.
+    Node(NodeSource),
+}
+
+impl Default for ZoneSource {
+    fn default() -> Self {
+        Self::Node(NodeSource::default())
+    }
+}
+
+impl ZoneSource {
+    /// Returns the zone of the client, or `None` if it is not known.
+    pub(crate) async fn resolve(&self, client: &Client) -> Result<Option<String>> {
+        match self {
+            Self::Zone(zone) => Ok(Some(zone.clone())),
+            Self::Env(var) => Ok(env_var(var)),
+            Self::Node(node) => {
+                let Some(name) = node.resolve() else {
+                    return Ok(None);
+                };
+
+                let nodes: Api<Node> = Api::all(client.clone());
+                let node = nodes.get(&name).await?;
+                Ok(node_zone(&node))
+            }
+        }
+    }
+}
+
+/// Configuration of zone-aware discovery.
+///
+/// Endpoints hinted for the client's zone are preferred. If the endpoints carry
+/// no hints, endpoints located in the client's zone are preferred instead. When
+/// fewer than `min_endpoints` such endpoints are selected, endpoints of all zones
+/// are used.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ZoneAware {
+    /// Where the zone of the client is read from.
+    pub zone: ZoneSource,
+
+    /// Minimum number of endpoints in the client's zone required to keep traffic
+    /// within it (at least 1).
+    pub min_endpoints: usize,
+}
+
+impl ZoneAware {
+    /// Creates a zone-aware configuration, reading the client's zone from the given source.
+    #[must_use]
+    pub fn new(zone: ZoneSource) -> Self {
+        Self {
+            zone,
+            min_endpoints: 1,
+        }
+    }
+
+    /// Sets the minimum number of endpoints in the client's zone required to keep
+    /// traffic within it.
+    #[must_use]
+    pub fn min_endpoints(mut self, min_endpoints: usize) -> Self {
+        self.min_endpoints = min_endpoints;
+        self
+    }
+}
+
+impl Default for ZoneAware {
+    fn default() -> Self {
+        Self::new(ZoneSource::default())
+    }
+}
+
+/// Whether traffic may leave the client's node.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NodeLocalMode {
+    /// Endpoints on the client's node, falling back to all endpoints when the node
+    /// has none.
+    #[default]
+    Prefer,
+
+    /// Only endpoints on the client's node, like `internalTrafficPolicy: Local`.
+    /// No endpoints are selected while the node has none.
+    Only,
+}
+
+/// Configuration of node-local discovery.
+///
+/// Endpoints are matched against the client's node by their `nodeName`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NodeLocal {
+    /// Where the name of the client's node is read from.
+    pub node: NodeSource,
+
+    /// Whether traffic may leave the client's node.
+    pub mode: NodeLocalMode,
+}
+
+impl NodeLocal {
+    /// Prefers endpoints on the client's node, falling back to all endpoints.
+    #[must_use]
+    pub fn prefer(node: NodeSource) -> Self {
+        Self {
+            node,
+            mode: NodeLocalMode::Prefer,
+        }
+    }
+
+    /// Only uses endpoints on the client's node.
+    #[must_use]
+    pub fn only(node: NodeSource) -> Self {
+        Self {
+            node,
+            mode: NodeLocalMode::Only,
+        }
+    }
+
+    /// Returns the preference for the client's node.
+    ///
+    /// If the node is unknown, endpoints on all nodes are preferred equally, unless
+    /// traffic must stay on the node.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::UnknownNode`] if traffic must stay on the node but its name
+    /// is not known.
+    pub(crate) fn resolve(&self) -> Result<Option<NodePreference>> {
+        match (self.node.resolve(), self.mode) {
+            (Some(node), mode) => Ok(Some(NodePreference { node, mode })),
+            (None, NodeLocalMode::Prefer) => Ok(None),
+            (None, NodeLocalMode::Only) => Err(Error::UnknownNode),
+        }
+    }
+}
+
+/// Preference for the endpoints of the client's node.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct NodePreference {
+    /// The name of the client's node.
+    pub(crate) node: String,
+
+    /// Whether traffic may leave the node.
+    pub(crate) mode: NodeLocalMode,
+}
+
+impl NodePreference {
+    /// Returns the endpoints on the node, or `None` if traffic may leave the node
+    /// and it has no endpoints.
+    pub(crate) fn apply<'a, K>(
+        &self,
+        endpoints: &[(&'a K, &'a DiscoveredEndpoint)],
+    ) -> Option<Vec<(&'a K, &'a DiscoveredEndpoint)>> {
+        let local: Vec<_> = endpoints
+            .iter()
+            .filter(|(_, endpoint)| endpoint.node_name.as_ref() == Some(&self.node))
+            .copied()
+            .collect();
+
+        (self.mode == NodeLocalMode::Only || !local.is_empty()).then_some(local)
+    }
+}
+
+/// Preference for the endpoints of the client's zone.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ZonePreference {
+    /// The zone of the client.
+    pub(crate) zone: String,
+
+    /// Minimum number of endpoints in the zone required to prefer it.
+    pub(crate) min_endpoints: usize,
+}
+
+impl ZonePreference {
+    /// Returns the endpoints of the zone, or all endpoints if the zone has too few.
+    ///
+    /// Like kube-proxy, hints are only used if every endpoint has them, as slices
+    /// are updated one by one while hints are being added or removed.
+    pub(crate) fn apply<'a, K>(
+        &self,
+        endpoints: Vec<(&'a K, &'a DiscoveredEndpoint)>,
+    ) -> Vec<(&'a K, &'a DiscoveredEndpoint)> {
+        let hinted = endpoints
+            .iter()
+            .all(|(_, endpoint)| !endpoint.hints_for_zones.is_empty());
+
+        let local: Vec<_> = endpoints
+            .iter()
+            .filter(|(_, endpoint)| {
+                if hinted {
+                    endpoint.hints_for_zones.contains(&self.zone)
+                } else {
+                    endpoint.zone.as_ref() == Some(&self.zone)
+                }
+            })
+            .copied()
+            .collect();
+
+        if local.len() >= self.min_endpoints.max(1) {
+            local
+        } else {
+            endpoints
+        }
+    }
+}
+
+/// Returns the value of an environment variable, if it is set and not empty.
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// Returns the zone of a `Node` from its labels.
+fn node_zone(node: &Node) -> Option<String> {
+    node.metadata.labels.as_ref()?.get(ZONE_LABEL).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::net::SocketAddr;
+
+    use kube::api::ObjectMeta;
+
+    use super::*;
+    use crate::k8s::test_client;
+
+    fn endpoint(addr: &str, zone: &str, hints: &[&str]) -> DiscoveredEndpoint {
+        let mut endpoint = DiscoveredEndpoint::new(addr.parse().unwrap());
+        endpoint.zone = Some(zone.to_string());
+        endpoint.hints_for_zones = hints.iter().map(ToString::to_string).collect();
+        endpoint
+    }
+
+    fn preference(zone: &str, min_endpoints: usize) -> ZonePreference {
+        ZonePreference {
+            zone: zone.to_string(),
+            min_endpoints,
+        }
+    }
+
+    // Applies the preference and returns the sorted addresses of the result
+    fn apply(preference: &ZonePreference, endpoints: &[DiscoveredEndpoint]) -> Vec<String> {
+        let keys: Vec<SocketAddr> = endpoints.iter().map(|e| e.address).collect();
+        let pairs = keys.iter().zip(endpoints).collect();
+
+        let mut addrs: Vec<String> = preference
+            .apply(pairs)
+            .into_iter()
+            .map(|(key, _)| key.to_string())
+            .collect();
+
+        addrs.sort();
+        addrs
+    }
+
+    // ZoneAware tests
+
+    #[test]
+    fn zone_aware_defaults() {
+        let zone_aware = ZoneAware::default();
+
+        assert_eq!(
+            zone_aware.zone,
+            ZoneSource::Node(NodeSource::Env("NODE_NAME".to_string()))
+        );
+        assert_eq!(zone_aware.min_endpoints, 1);
+    }
+
+    #[test]
+    fn zone_aware_builder() {
+        let zone_aware = ZoneAware::new(ZoneSource::Env("ZONE".to_string())).min_endpoints(3);
+
+        assert_eq!(zone_aware.zone, ZoneSource::Env("ZONE".to_string()));
+        assert_eq!(zone_aware.min_endpoints, 3);
+    }
+
+    // ZoneSource tests
+
+    #[test]
+    fn node_zone_reads_label() {
+        let node = Node {
+            metadata: ObjectMeta {
+                labels: Some(BTreeMap::from([(
+                    ZONE_LABEL.to_string(),
+                    "us-east-1a".to_string(),
+                )])),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        assert_eq!(node_zone(&node).as_deref(), Some("us-east-1a"));
+        assert_eq!(node_zone(&Node::default()), None);
+    }
+
+    #[tokio::test]
+    async fn resolve_configured_zone() {
+        let source = ZoneSource::Zone("us-east-1a".to_string());
+        let zone = source.resolve(&test_client()).await.unwrap();
+
+        assert_eq!(zone.as_deref(), Some("us-east-1a"));
+    }
+
+    #[tokio::test]
+    async fn resolve_without_node_name_is_unknown() {
+        // The Node is not looked up without a name
+        let source = ZoneSource::Node(NodeSource::Env("TONIC_LB_K8S_TEST_UNSET_NODE".to_string()));
+        let zone = source.resolve(&test_client()).await.unwrap();
+
+        assert_eq!(zone, None);
+    }
+
+    #[test]
+    fn env_var_ignores_unset_variable() {
+        assert_eq!(env_var("TONIC_LB_K8S_TEST_UNSET_ZONE"), None);
+    }
+
+    // NodeLocal tests
+
+    #[test]
+    fn node_local_resolves_node_name() {
+        let node_local = NodeLocal::only(NodeSource::Name("node-1".to_string()));
+
+        assert_eq!(
+            node_local.resolve().unwrap(),
+            Some(NodePreference {
+                node: "node-1".to_string(),
+                mode: NodeLocalMode::Only,
+            })
+        );
+    }
+
+    #[test]
+    fn node_local_with_unknown_node() {
+        let node = NodeSource::Env("TONIC_LB_K8S_TEST_UNSET_NODE".to_string());
+
+        assert_eq!(NodeLocal::prefer(node.clone()).resolve().unwrap(), None);
+        assert!(matches!(
+            NodeLocal::only(node).resolve(),
+            Err(Error::UnknownNode)
+        ));
+    }
+
+    // NodePreference tests
+
+    fn on_node(addr: &str, node: &str) -> DiscoveredEndpoint {
+        let mut endpoint = DiscoveredEndpoint::new(addr.parse().unwrap());
+        endpoint.node_name = Some(node.to_string());
+        endpoint
+    }
+
+    // Applies the node preference and returns the sorted addresses of the result
+    fn apply_node(
+        preference: &NodePreference,
+        endpoints: &[DiscoveredEndpoint],
+    ) -> Option<Vec<String>> {
+        let keys: Vec<SocketAddr> = endpoints.iter().map(|e| e.address).collect();
+        let pairs: Vec<_> = keys.iter().zip(endpoints).collect();
+
+        preference.apply(&pairs).map(|local| {
+            let mut addrs: Vec<String> = local.iter().map(|(key, _)| key.to_string()).collect();
+            addrs.sort();
+            addrs
+        })
+    }
+
+    fn node_preference(node: &str, mode: NodeLocalMode) -> NodePreference {
+        NodePreference {
+            node: node.to_string(),
+            mode,
+        }
+    }
+
+    #[test]
+    fn node_prefer_selects_local_endpoints() {
+        let endpoints = [
+            on_node("10.0.0.1:80", "node-1"),
+            on_node("10.0.0.2:80", "node-2"),
+        ];
+
+        let preference = node_preference("node-1", NodeLocalMode::Prefer);
+        assert_eq!(
+            apply_node(&preference, &endpoints),
+            Some(vec!["10.0.0.1:80".to_string()])
+        );
+    }
+
+    #[test]
+    fn node_prefer_falls_back_without_local_endpoints() {
+        let endpoints = [on_node("10.0.0.2:80", "node-2")];
+
+        let preference = node_preference("node-1", NodeLocalMode::Prefer);
+        assert_eq!(apply_node(&preference, &endpoints), None);
+    }
+
+    #[test]
+    fn node_only_never_falls_back() {
+        let endpoints = [on_node("10.0.0.2:80", "node-2")];
+
+        let preference = node_preference("node-1", NodeLocalMode::Only);
+        assert_eq!(apply_node(&preference, &endpoints), Some(Vec::new()));
+    }
+
+    // ZonePreference tests
+
+    #[test]
+    fn prefers_endpoints_hinted_for_zone() {
+        // The hint overrides the zone the endpoint is located in
+        let endpoints = [
+            endpoint("10.0.0.1:80", "a", &["a"]),
+            endpoint("10.0.0.2:80", "b", &["a"]),
+            endpoint("10.0.0.3:80", "a", &["b"]),
+        ];
+
+        assert_eq!(
+            apply(&preference("a", 1), &endpoints),
+            vec!["10.0.0.1:80", "10.0.0.2:80"]
+        );
+    }
+
+    #[test]
+    fn prefers_endpoints_in_zone_without_hints() {
+        let endpoints = [
+            endpoint("10.0.0.1:80", "a", &[]),
+            endpoint("10.0.0.2:80", "b", &[]),
+        ];
+
+        assert_eq!(apply(&preference("a", 1), &endpoints), vec!["10.0.0.1:80"]);
+    }
+
+    #[test]
+    fn ignores_hints_unless_every_endpoint_has_them() {
+        let endpoints = [
+            endpoint("10.0.0.1:80", "a", &["b"]),
+            endpoint("10.0.0.2:80", "b", &[]),
+        ];
+
+        assert_eq!(apply(&preference("a", 1), &endpoints), vec!["10.0.0.1:80"]);
+    }
+
+    #[test]
+    fn falls_back_to_all_zones_with_too_few_endpoints() {
+        let endpoints = [
+            endpoint("10.0.0.1:80", "a", &[]),
+            endpoint("10.0.0.2:80", "b", &[]),
+            endpoint("10.0.0.3:80", "b", &[]),
+        ];
+
+        assert_eq!(
+            apply(&preference("a", 2), &endpoints),
+            vec!["10.0.0.1:80", "10.0.0.2:80", "10.0.0.3:80"]
+        );
+        assert_eq!(apply(&preference("c", 0), &endpoints).len(), 3);
+    }
+}
@@ -0,0 +1,309 @@
+//! Endpoint weights read from pod metadata.
+//!
+//! `EndpointSlice` resources do not carry pod metadata, so the pods carrying
+//! weights are watched as well and their weights cached by name. Services in the
+//! same namespace share the watch and its cache. When a pod's weight changes, its
+//! endpoints are re-weighed.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use k8s_openapi::api::core::v1::Pod;
+use kube::core::{ObjectMeta, PartialObjectMeta};
+use kube::runtime::watcher::{Config as WatcherConfig, Event};
+use tracing::warn;
+
+use crate::endpoint::DiscoveredEndpoint;
+
+/// Pod metadata holding the weight of its endpoints.
+///
+/// The value must be a positive integer. Endpoints of pods without a valid weight
+/// have a weight of 1.
+///
+/// Only the pods that may carry a weight are watched: those matching the label
+/// selector of an annotation, or having the label.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WeightSource {
+    /// The annotation of the pods matching a label selector.
+    Annotation {
+        /// The annotation key.
+        key: String,
+
+        /// The label selector of the pods, e.g. the Service's pod selector.
+        selector: String,
+    },
+
+    /// The pod label with the given key.
+    Label(String),
+}
+
+impl WeightSource {
+    /// Returns the watcher configuration selecting the pods that may carry a weight.
+    pub(crate) fn watcher_config(&self) -> WatcherConfig {
+        match self {
+            Self::Annotation { selector, .. } => WatcherConfig::default().labels(selector),
+            Self::Label(key) => WatcherConfig::default().labels(key),
+        }
+    }
+
+    /// Returns the weight set in a pod's metadata, if it is valid.
+    fn weight(&self, meta: &ObjectMeta) -> Option<u32> {
+        let (values, key) = match self {
+            Self::Annotation { key, .. } => (meta.annotations.as_ref(), key),
+            Self::Label(key) => (meta.labels.as_ref(), key),
+        };
+
+        let value = values?.get(key)?;
+        let weight = value.trim().parse().ok().filter(|weight| *weight > 0);
+        if weight.is_none() {
+            warn!(
+                "ignoring invalid weight {value:?} of pod {}/{}",
+                meta.namespace.as_deref().unwrap_or_default(),
+                meta.name.as_deref().unwrap_or_default()
+            );
+        }
+
+        weight
+    }
+}
+
+/// Namespace and name of a pod.
+type PodName = (String, String);
+
+/// Weights of the pods shared by the Services whose pods are watched together.
+pub(crate) type SharedWeights = Arc<Mutex<Weights>>;
+
+/// Weights of the pods backing the endpoints of the watched Services.
+#[derive(Debug, Default)]
+pub(crate) struct Weights {
+    /// Pod metadata holding the weight, if endpoints are weighted.
+    source: Option<WeightSource>,
+
+    /// Weights of the pods that have one.
+    pods: HashMap<PodName, u32>,
+
+    /// Weights received since the last `Init` event, if a re-list is in progress.
+    pending: Option<HashMap<PodName, u32>>,
+}
+
+impl Weights {
+    /// Creates an empty cache for the given source.
+    pub(crate) fn new(source: Option<WeightSource>) -> Self {
+        Self {
+            source,
+            ..Self::default()
+        }
+    }
+
+    /// Returns whether endpoints are weighted.
+    pub(crate) fn enabled(&self) -> bool {
+        self.source.is_some()
+    }
+
+    /// Returns the weight of an endpoint, from its backing pod.
+    pub(crate) fn weight(&self, endpoint: &DiscoveredEndpoint) -> u32 {
+        let pod = endpoint
+            .target_ref
+            .as_ref()
+            .filter(|target| target.kind.as_deref() == Some("Pod"))
+            .and_then(|target| Some((target.namespace.clone()?, target.name.clone()?)));
+
+        pod.and_then(|pod| self.pods.get(&pod).copied())
+            .unwrap_or(1)
+    }
+
+    /// Applies a pod watcher event and returns whether any weight changed.
+    pub(crate) fn apply(&mut self, event: &Event<PartialObjectMeta<Pod>>) -> bool {
+        let Some(source) = &self.source else {
+            return false;
+        };
+
+        match event {
+            Event::Apply(pod) => {
+                let name = pod_name(&pod.metadata);
+                let weight = source.weight(&pod.metadata);
+                let previous = match weight {
+                    Some(weight) => self.pods.insert(name, weight),
+                    None => self.pods.remove(&name),
+                };
+
+                previous != weight
+            }
+
+            Event::Delete(pod) => self.pods.remove(&pod_name(&pod.metadata)).is_some(),
+
+            Event::Init => {
+                self.pending = Some(HashMap::new());
+                false
+            }
+
+            Event::InitApply(pod) => {
+                let weight = source.weight(&pod.metadata);
+                if let (Some(pending), Some(weight)) = (&mut self.pending, weight) {
+                    pending.insert(pod_name(&pod.metadata), weight);
+                }
+
+                false
+            }
+
+            Event::InitDone => {
+                let Some(pods) = self.pending.take() else {
+                    return false;
+                };
+
+                let changed = pods != self.pods;
+                self.pods = pods;
+                changed
+            }
+        }
+    }
+}
+
+/// Returns the namespace and name of a pod.
+fn pod_name(meta: &ObjectMeta) -> PodName {
+    (
+        meta.namespace.clone().unwrap_or_default(),
+        meta.name.clone().unwrap_or_default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::endpoint::TargetRef;
+
+    use super::*;
+
+    fn pod(name: &str, annotations: &[(&str, &str)]) -> PartialObjectMeta<Pod> {
+        let annotations = annotations
+            .iter()
+            .map(|(key, value)| ((*key).to_string(), (*value).to_string()))
+            .collect::<BTreeMap<_, _>>();
+
+        PartialObjectMeta {
+            metadata: ObjectMeta {
+                namespace: Some("default".to_string()),
+                name: Some(name.to_string()),
+                annotations: Some(annotations),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn endpoint(pod: &str) -> DiscoveredEndpoint {
+        let mut endpoint = DiscoveredEndpoint::new("10.0.0.1:80".parse().unwrap());
+        endpoint.target_ref = Some(TargetRef {
+            kind: Some("Pod".to_string()),
+            namespace: Some("default".to_string()),
+            name: Some(pod.to_string()),
+            ..Default::default()
+        });
+
+        endpoint
+    }
+
+    fn annotation() -> WeightSource {
+        WeightSource::Annotation {
+            key: "weight".to_string(),
+            selector: "app=my-app".to_string(),
+        }
+    }
+
+    fn weights() -> Weights {
+        Weights::new(Some(annotation()))
+    }
+
+    // WeightSource tests
+
+    #[test]
+    fn source_watches_pods_that_may_carry_weight() {
+        assert_eq!(
+            annotation().watcher_config().label_selector.as_deref(),
+            Some("app=my-app")
+        );
+        assert_eq!(
+            WeightSource::Label("weight".to_string())
+                .watcher_config()
+                .label_selector
+                .as_deref(),
+            Some("weight")
+        );
+    }
+
+    #[test]
+    fn source_reads_annotation_or_label() {
+        let meta = ObjectMeta {
+            annotations: Some(BTreeMap::from([("weight".to_string(), "3".to_string())])),
+            labels: Some(BTreeMap::from([("weight".to_string(), "5".to_string())])),
+            ..Default::default()
+        };
+
+        assert_eq!(annotation().weight(&meta), Some(3));
+        assert_eq!(
+            WeightSource::Label("weight".to_string()).weight(&meta),
+            Some(5)
+        );
+    }
+
+    #[test]
+    fn source_rejects_invalid_weights() {
+        let source = annotation();
+
+        for value in ["0", "-1", "heavy", ""] {
+            let meta = pod("pod-1", &[("weight", value)]).metadata;
+            assert_eq!(source.weight(&meta), None, "{value:?}");
+        }
+    }
+
+    // Weights tests
+
+    #[test]
+    fn weights_default_to_one() {
+        let mut weights = weights();
+        weights.apply(&Event::Apply(pod("pod-1", &[])));
+
+        assert_eq!(weights.weight(&endpoint("pod-1")), 1);
+        assert_eq!(
+            weights.weight(&DiscoveredEndpoint::new("10.0.0.1:80".parse().unwrap())),
+            1
+        );
+    }
+
+    #[test]
+    fn weights_track_pod_changes() {
+        let mut weights = weights();
+
+        assert!(weights.apply(&Event::Apply(pod("pod-1", &[("weight", "4")]))));
+        assert_eq!(weights.weight(&endpoint("pod-1")), 4);
+
+        // Unrelated pod updates do not change any weight
+        assert!(!weights.apply(&Event::Apply(pod("pod-1", &[("weight", "4")]))));
+
+        assert!(weights.apply(&Event::Delete(pod("pod-1", &[("weight", "4")]))));
+        assert_eq!(weights.weight(&endpoint("pod-1")), 1);
+    }
+
+    #[test]
+    fn weights_replace_pods_after_relist() {
+        let mut weights = weights();
+        weights.apply(&Event::Apply(pod("pod-1", &[("weight", "4")])));
+
+        weights.apply(&Event::Init);
+        weights.apply(&Event::InitApply(pod("pod-2", &[("weight", "2")])));
+        assert_eq!(weights.weight(&endpoint("pod-1")), 4);
+
+        assert!(weights.apply(&Event::InitDone));
+        assert_eq!(weights.weight(&endpoint("pod-1")), 1);
+        assert_eq!(weights.weight(&endpoint("pod-2")), 2);
+    }
+
+    #[test]
+    fn weights_without_source_ignore_pods() {
+        let mut weights = Weights::default();
+
+        assert!(!weights.apply(&Event::Apply(pod("pod-1", &[("weight", "4")]))));
+        assert_eq!(weights.weight(&endpoint("pod-1")), 1);
+    }
+}